@@ -3,11 +3,13 @@ use reqwest_eventsource::{Event, EventSource};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut es = EventSource::get("http://localhost:8000/events");
+    let mut es = EventSource::get("http://localhost:8000/events")?;
     while let Some(event) = es.next().await {
         match event {
-            Ok(Event::Open) => println!("Connection Open!"),
+            Ok(Event::Open(_)) => println!("Connection Open!"),
             Ok(Event::Message(message)) => println!("Message: {:#?}", message),
+            Ok(Event::Closed) => println!("Connection Closed!"),
+            Ok(Event::Comment(_)) => {}
             Err(err) => {
                 println!("Error: {}", err);
                 // es.close();
@@ -0,0 +1,467 @@
+//! [`Stream`] adapters over the [`Event`] items of an [`EventSource`]
+
+use crate::error::Error;
+use crate::event::{Event, MessageEvent, SimpleEvent};
+use crate::event_source::{BoxedRetry, EventSource};
+use crate::retry::RetryPolicy;
+use crate::timer::Timeout;
+use core::future::Future;
+#[cfg(feature = "json")]
+use core::marker::PhantomData;
+use core::pin::Pin;
+use futures_core::ready;
+use futures_core::stream::{FusedStream, Stream};
+use futures_core::task::{Context, Poll};
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+/// A [`Stream`] of only the [`MessageEvent`]s of an [`EventSource`], created by
+/// [`EventSource::messages`]. Other events are skipped
+pub struct Messages<R = BoxedRetry> {
+    source: EventSource<R>,
+}
+
+impl<R> Messages<R> {
+    pub(crate) fn new(source: EventSource<R>) -> Self {
+        Self { source }
+    }
+
+    /// Get a reference to the underlying [`EventSource`]
+    pub fn get_ref(&self) -> &EventSource<R> {
+        &self.source
+    }
+
+    /// Get a mutable reference to the underlying [`EventSource`], e.g. to close it
+    pub fn get_mut(&mut self) -> &mut EventSource<R> {
+        &mut self.source
+    }
+
+    /// Get back the underlying [`EventSource`]
+    pub fn into_inner(self) -> EventSource<R> {
+        self.source
+    }
+}
+
+impl<R: RetryPolicy> Stream for Messages<R> {
+    type Item = Result<MessageEvent, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.source).poll_next(cx)) {
+                Some(Ok(Event::Message(message))) => return Poll::Ready(Some(Ok(message))),
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl<R: RetryPolicy> FusedStream for Messages<R> {
+    fn is_terminated(&self) -> bool {
+        self.source.is_terminated()
+    }
+}
+
+/// A [`Stream`] of only the messages of an [`EventSource`] as [`SimpleEvent`]s, created by
+/// [`EventSource::messages_simple`]. Other events are skipped
+pub struct SimpleMessages<R = BoxedRetry> {
+    messages: Messages<R>,
+}
+
+impl<R> SimpleMessages<R> {
+    pub(crate) fn new(source: EventSource<R>) -> Self {
+        Self {
+            messages: Messages::new(source),
+        }
+    }
+
+    /// Get a reference to the underlying [`EventSource`]
+    pub fn get_ref(&self) -> &EventSource<R> {
+        self.messages.get_ref()
+    }
+
+    /// Get a mutable reference to the underlying [`EventSource`], e.g. to close it
+    pub fn get_mut(&mut self) -> &mut EventSource<R> {
+        self.messages.get_mut()
+    }
+
+    /// Get back the underlying [`EventSource`]
+    pub fn into_inner(self) -> EventSource<R> {
+        self.messages.into_inner()
+    }
+}
+
+impl<R: RetryPolicy> Stream for SimpleMessages<R> {
+    type Item = Result<SimpleEvent, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match ready!(Pin::new(&mut self.messages).poll_next(cx)) {
+            Some(Ok(message)) => Poll::Ready(Some(Ok(message.into()))),
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl<R: RetryPolicy> FusedStream for SimpleMessages<R> {
+    fn is_terminated(&self) -> bool {
+        self.messages.is_terminated()
+    }
+}
+
+/// A [`Stream`] combining consecutive messages into one, created by
+/// [`EventSource::coalesce_until`]. The data of the messages is concatenated until a message's
+/// data matches the predicate, and the combined message takes the other fields of the last one.
+/// Buffered data is yielded before any other event, error or the end of the stream
+pub struct CoalesceUntil<F, R = BoxedRetry> {
+    source: EventSource<R>,
+    predicate: F,
+    buffer: Option<MessageEvent>,
+    pending: Option<Result<Event, Error>>,
+}
+
+impl<F, R> CoalesceUntil<F, R> {
+    pub(crate) fn new(source: EventSource<R>, predicate: F) -> Self {
+        Self {
+            source,
+            predicate,
+            buffer: None,
+            pending: None,
+        }
+    }
+
+    /// Get a reference to the underlying [`EventSource`]
+    pub fn get_ref(&self) -> &EventSource<R> {
+        &self.source
+    }
+
+    /// Get a mutable reference to the underlying [`EventSource`], e.g. to close it
+    pub fn get_mut(&mut self) -> &mut EventSource<R> {
+        &mut self.source
+    }
+
+    /// Get back the underlying [`EventSource`]. Buffered data is lost
+    pub fn into_inner(self) -> EventSource<R> {
+        self.source
+    }
+}
+
+impl<F, R> Stream for CoalesceUntil<F, R>
+where
+    F: FnMut(&str) -> bool + Unpin,
+    R: RetryPolicy,
+{
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some(item) = self.pending.take() {
+            return Poll::Ready(Some(item));
+        }
+        loop {
+            match ready!(Pin::new(&mut self.source).poll_next(cx)) {
+                Some(Ok(Event::Message(message))) => {
+                    let done = (self.predicate)(&message.data);
+                    let message = match self.buffer.take() {
+                        Some(mut buffer) => {
+                            buffer.data.push_str(&message.data);
+                            buffer.event = message.event;
+                            buffer.id = message.id;
+                            buffer.retry = message.retry.or(buffer.retry);
+                            buffer
+                        }
+                        None => message,
+                    };
+                    if done {
+                        return Poll::Ready(Some(Ok(Event::Message(message))));
+                    }
+                    self.buffer = Some(message);
+                }
+                Some(Ok(Event::Comment(comment))) => {
+                    return Poll::Ready(Some(Ok(Event::Comment(comment))))
+                }
+                item => {
+                    return match self.buffer.take() {
+                        Some(buffer) => {
+                            self.pending = item;
+                            Poll::Ready(Some(Ok(Event::Message(buffer))))
+                        }
+                        None => Poll::Ready(item),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl<F, R> FusedStream for CoalesceUntil<F, R>
+where
+    F: FnMut(&str) -> bool + Unpin,
+    R: RetryPolicy,
+{
+    fn is_terminated(&self) -> bool {
+        self.buffer.is_none() && self.pending.is_none() && self.source.is_terminated()
+    }
+}
+
+/// A [`Stream`] collecting the events of an [`EventSource`] into batches, created by
+/// [`EventSource::batched`]. A batch is yielded when it holds `max` events or `max_delay` after its
+/// first event, whichever comes first. On an error or the end of the stream, the current batch is
+/// yielded first, then the error or the end
+pub struct Batched<R = BoxedRetry> {
+    source: EventSource<R>,
+    max: usize,
+    max_delay: Duration,
+    batch: Vec<Event>,
+    delay: Option<Timeout>,
+    pending_error: Option<Error>,
+    ended: bool,
+}
+
+impl<R> Batched<R> {
+    pub(crate) fn new(source: EventSource<R>, max: usize, max_delay: Duration) -> Self {
+        Self {
+            source,
+            max: max.max(1),
+            max_delay,
+            batch: Vec::new(),
+            delay: None,
+            pending_error: None,
+            ended: false,
+        }
+    }
+
+    /// Get a reference to the underlying [`EventSource`]
+    pub fn get_ref(&self) -> &EventSource<R> {
+        &self.source
+    }
+
+    /// Get a mutable reference to the underlying [`EventSource`], e.g. to close it
+    pub fn get_mut(&mut self) -> &mut EventSource<R> {
+        &mut self.source
+    }
+
+    /// Get back the underlying [`EventSource`]. Buffered events are lost
+    pub fn into_inner(self) -> EventSource<R> {
+        self.source
+    }
+
+    fn flush(&mut self) -> Vec<Event> {
+        self.delay = None;
+        std::mem::take(&mut self.batch)
+    }
+}
+
+impl<R: RetryPolicy> Stream for Batched<R> {
+    type Item = Result<Vec<Event>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some(err) = self.pending_error.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+        if self.ended {
+            return Poll::Ready(None);
+        }
+        loop {
+            match Pin::new(&mut self.source).poll_next(cx) {
+                Poll::Ready(Some(Ok(event))) => {
+                    if self.batch.is_empty() {
+                        self.delay = Some(self.source.clock().delay(self.max_delay));
+                    }
+                    self.batch.push(event);
+                    if self.batch.len() >= self.max {
+                        return Poll::Ready(Some(Ok(self.flush())));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    if self.batch.is_empty() {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                    self.pending_error = Some(err);
+                    return Poll::Ready(Some(Ok(self.flush())));
+                }
+                Poll::Ready(None) => {
+                    self.ended = true;
+                    if self.batch.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Ready(Some(Ok(self.flush())));
+                }
+                Poll::Pending => {
+                    return match self.delay.as_mut() {
+                        Some(delay) => {
+                            ready!(Pin::new(delay).poll(cx));
+                            Poll::Ready(Some(Ok(self.flush())))
+                        }
+                        None => Poll::Pending,
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl<R: RetryPolicy> FusedStream for Batched<R> {
+    fn is_terminated(&self) -> bool {
+        self.ended && self.pending_error.is_none()
+    }
+}
+
+/// A [`Stream`] deserializing the data of each message as JSON, created by
+/// [`EventSource::json`]. Other events are skipped. Requires the `json` feature
+#[cfg(feature = "json")]
+pub struct Json<T, R = BoxedRetry> {
+    source: EventSource<R>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "json")]
+impl<T, R> Json<T, R> {
+    pub(crate) fn new(source: EventSource<R>) -> Self {
+        Self {
+            source,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get a reference to the underlying [`EventSource`]
+    pub fn get_ref(&self) -> &EventSource<R> {
+        &self.source
+    }
+
+    /// Get a mutable reference to the underlying [`EventSource`], e.g. to close it
+    pub fn get_mut(&mut self) -> &mut EventSource<R> {
+        &mut self.source
+    }
+
+    /// Get back the underlying [`EventSource`]
+    pub fn into_inner(self) -> EventSource<R> {
+        self.source
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned, R: RetryPolicy> Stream for Json<T, R> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.source).poll_next(cx)) {
+                Some(Ok(Event::Message(message))) => {
+                    return Poll::Ready(Some(
+                        serde_json::from_str(&message.data).map_err(Error::Deserialize),
+                    ));
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned, R: RetryPolicy> FusedStream for Json<T, R> {
+    fn is_terminated(&self) -> bool {
+        self.source.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{pending_until, MockClock, Reply, Server};
+    use futures::StreamExt;
+
+    fn source(server: &Server, clock: &MockClock) -> EventSource {
+        EventSource::builder(reqwest::Client::new().get(server.url()))
+            .emit_open(false)
+            .clock(clock.clone())
+            .build()
+            .unwrap()
+            .without_retries()
+    }
+
+    fn data(batch: Vec<Event>) -> Vec<String> {
+        batch
+            .into_iter()
+            .map(|event| event.into_message().unwrap().data)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn batched_flushes_when_full() {
+        let server = Server::new(|_| Reply::events("data: a\n\ndata: b\n\ndata: c\n\n"));
+        let clock = MockClock::new();
+        let mut batched = source(&server, &clock).batched(2, Duration::from_secs(1));
+        assert_eq!(data(batched.next().await.unwrap().unwrap()), ["a", "b"]);
+        // the rest is yielded before the error ending the stream
+        assert_eq!(data(batched.next().await.unwrap().unwrap()), ["c"]);
+        assert!(matches!(
+            batched.next().await,
+            Some(Err(Error::StreamEnded))
+        ));
+        assert_eq!(batched.next().await.unwrap().unwrap(), [Event::Closed]);
+        assert!(batched.next().await.is_none());
+        assert!(batched.is_terminated());
+    }
+
+    #[tokio::test]
+    async fn batched_flushes_after_max_delay() {
+        let server = Server::new(|_| Reply::events("data: a\n\n").hold());
+        let clock = MockClock::new();
+        let mut batched = source(&server, &clock).batched(10, Duration::from_secs(1));
+        let (batch, _) = futures::join!(
+            batched.next(),
+            clock.advance_after(1, Duration::from_secs(1))
+        );
+        assert_eq!(data(batch.unwrap().unwrap()), ["a"]);
+        assert_eq!(clock.sleeps(), 1);
+    }
+
+    #[tokio::test]
+    async fn batched_flushes_on_close() {
+        let server = Server::new(|_| Reply::events("data: a\n\n").hold());
+        let clock = MockClock::new();
+        let mut batched = source(&server, &clock).batched(10, Duration::from_secs(1));
+        pending_until(&mut batched, |batched| {
+            batched.get_ref().stats().events == 1
+        })
+        .await;
+        batched.get_mut().close();
+        let batch = batched.next().await.unwrap().unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].data(), Some("a"));
+        assert_eq!(batch[1], Event::Closed);
+        assert!(batched.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn coalesce_until_predicate_matches() {
+        let server = Server::new(|_| {
+            Reply::events("id: 1\ndata: a\n\nid: 2\ndata: b.\n\ndata: c.\n\n: ping\ndata: d\n\n")
+        });
+        let clock = MockClock::new();
+        let mut coalesced = source(&server, &clock).coalesce_until(|data| data.ends_with('.'));
+        let message = coalesced
+            .next()
+            .await
+            .unwrap()
+            .unwrap()
+            .into_message()
+            .unwrap();
+        assert_eq!(message.data, "ab.");
+        assert_eq!(message.id, "2");
+        assert_eq!(coalesced.next().await.unwrap().unwrap().data(), Some("c."));
+        // the buffered data is yielded before the error ending the stream
+        assert_eq!(coalesced.next().await.unwrap().unwrap().data(), Some("d"));
+        assert!(matches!(
+            coalesced.next().await,
+            Some(Err(Error::StreamEnded))
+        ));
+        assert_eq!(coalesced.next().await.unwrap().unwrap(), Event::Closed);
+        assert!(coalesced.next().await.is_none());
+        assert!(coalesced.is_terminated());
+    }
+}
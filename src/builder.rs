@@ -0,0 +1,421 @@
+use crate::error::{CannotCloneRequestError, Error};
+use crate::event_source::{
+    default_accept_content_type, default_accept_status, BoxedAcceptContentType, BoxedAcceptStatus,
+    BoxedBeforeRequest, BoxedBodyFactory, BoxedConnectTimeout, BoxedHeaderProvider, BoxedOnError,
+    BoxedOnReconnect, BoxedOnResponse, BoxedRetry, EventSource, HeaderFuture, StatusAction,
+};
+use crate::retry::{RetryPolicy, DEFAULT_RETRY};
+use crate::timer::{BoxedClock, Clock};
+#[cfg(doc)]
+use crate::{event::Event, event_source::CloseReason};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Body, Client, IntoUrl, Method, RequestBuilder, Response, StatusCode};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Builds an [`EventSource`] from a request and its configuration in one place.
+///
+/// The request is cloned on every reconnect, so the body must be cloneable (e.g. a [`String`] or
+/// [`Vec<u8>`]). [`EventSourceBuilder::build`] fails with a [`CannotCloneRequestError`] for
+/// streaming bodies, which can instead be regenerated with [`EventSourceBuilder::body_factory`].
+pub struct EventSourceBuilder<R = BoxedRetry> {
+    pub(crate) builder: RequestBuilder,
+    pub(crate) retry_policy: R,
+    pub(crate) last_event_id: String,
+    pub(crate) treat_stream_end_as_error: bool,
+    pub(crate) connect_timeout: Option<BoxedConnectTimeout>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) heartbeat_timeout: Option<Duration>,
+    pub(crate) max_lifetime: Option<Duration>,
+    pub(crate) accept_status: BoxedAcceptStatus,
+    pub(crate) accept_content_type: BoxedAcceptContentType,
+    pub(crate) on_reconnect: Option<BoxedOnReconnect>,
+    pub(crate) on_response: Option<BoxedOnResponse>,
+    pub(crate) on_error: Option<BoxedOnError>,
+    pub(crate) reset_backoff_after: Option<Duration>,
+    pub(crate) follow_redirect_target: bool,
+    pub(crate) max_event_size: Option<usize>,
+    pub(crate) max_field_size: Option<usize>,
+    pub(crate) lenient_parsing: bool,
+    pub(crate) report_invalid_retry: bool,
+    pub(crate) report_partial_events: bool,
+    pub(crate) emit_comments: bool,
+    pub(crate) emit_open: bool,
+    pub(crate) accept: Option<HeaderValue>,
+    pub(crate) header_provider: Option<BoxedHeaderProvider>,
+    pub(crate) body_factory: Option<BoxedBodyFactory>,
+    pub(crate) before_request: Option<BoxedBeforeRequest>,
+    pub(crate) clock: Option<BoxedClock>,
+}
+
+impl EventSourceBuilder {
+    /// Wrap a [`RequestBuilder`]
+    pub fn new(builder: RequestBuilder) -> Self {
+        Self {
+            builder,
+            retry_policy: Box::new(DEFAULT_RETRY),
+            last_event_id: String::new(),
+            treat_stream_end_as_error: true,
+            connect_timeout: None,
+            idle_timeout: None,
+            heartbeat_timeout: None,
+            max_lifetime: None,
+            accept_status: Box::new(default_accept_status),
+            accept_content_type: Box::new(default_accept_content_type),
+            on_reconnect: None,
+            on_response: None,
+            on_error: None,
+            reset_backoff_after: None,
+            follow_redirect_target: false,
+            max_event_size: None,
+            max_field_size: None,
+            lenient_parsing: false,
+            report_invalid_retry: false,
+            report_partial_events: false,
+            emit_comments: false,
+            emit_open: true,
+            accept: Some(HeaderValue::from_static("text/event-stream")),
+            header_provider: None,
+            body_factory: None,
+            before_request: None,
+            clock: None,
+        }
+    }
+
+    /// Create a builder for a request with the given method
+    pub fn request<T: IntoUrl>(method: Method, url: T) -> Self {
+        Self::new(Client::new().request(method, url))
+    }
+
+    /// Create a builder for a GET request
+    pub fn get<T: IntoUrl>(url: T) -> Self {
+        Self::request(Method::GET, url)
+    }
+
+    /// Create a builder for a POST request
+    pub fn post<T: IntoUrl>(url: T) -> Self {
+        Self::request(Method::POST, url)
+    }
+}
+
+impl<R> EventSourceBuilder<R> {
+    /// Add a header to the request
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.builder = self.builder.header(key, value);
+        self
+    }
+
+    /// Add a set of headers to the request
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.builder = self.builder.headers(headers);
+        self
+    }
+
+    /// Set the request body
+    pub fn body<T: Into<Body>>(mut self, body: T) -> Self {
+        self.builder = self.builder.body(body);
+        self
+    }
+
+    /// Set the retry policy
+    pub fn retry_policy(mut self, policy: R) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Set a retry policy of another type, which the [`EventSource`] stores inline instead of
+    /// boxed. This avoids an allocation and dynamic dispatch per source
+    pub fn retry_policy_inline<P: RetryPolicy>(self, policy: P) -> EventSourceBuilder<P> {
+        EventSourceBuilder {
+            builder: self.builder,
+            retry_policy: policy,
+            last_event_id: self.last_event_id,
+            treat_stream_end_as_error: self.treat_stream_end_as_error,
+            connect_timeout: self.connect_timeout,
+            idle_timeout: self.idle_timeout,
+            heartbeat_timeout: self.heartbeat_timeout,
+            max_lifetime: self.max_lifetime,
+            accept_status: self.accept_status,
+            accept_content_type: self.accept_content_type,
+            on_reconnect: self.on_reconnect,
+            on_response: self.on_response,
+            on_error: self.on_error,
+            reset_backoff_after: self.reset_backoff_after,
+            follow_redirect_target: self.follow_redirect_target,
+            max_event_size: self.max_event_size,
+            max_field_size: self.max_field_size,
+            lenient_parsing: self.lenient_parsing,
+            report_invalid_retry: self.report_invalid_retry,
+            report_partial_events: self.report_partial_events,
+            emit_comments: self.emit_comments,
+            emit_open: self.emit_open,
+            accept: self.accept,
+            header_provider: self.header_provider,
+            body_factory: self.body_factory,
+            before_request: self.before_request,
+            clock: self.clock,
+        }
+    }
+
+    /// Set the last event id sent in the `Last-Event-ID` header of the first request, to resume a
+    /// stream from a persisted position
+    pub fn last_event_id(mut self, id: impl Into<String>) -> Self {
+        self.last_event_id = id.into();
+        self
+    }
+
+    /// Set whether the server ending the stream yields an [`Error::StreamEnded`] and goes through
+    /// the retry policy, which is the default. When disabled, the end of the stream closes the
+    /// [`EventSource`] with an [`Event::Closed`] instead
+    pub fn treat_stream_end_as_error(mut self, enabled: bool) -> Self {
+        self.treat_stream_end_as_error = enabled;
+        self
+    }
+
+    /// Set a timeout for receiving the response headers of each connection attempt. When it
+    /// elapses, an [`Error::ConnectTimeout`] is yielded and goes through the retry policy
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        self.connect_timeout_fn(move |_| timeout)
+    }
+
+    /// Like [`EventSourceBuilder::connect_timeout`], but the timeout of each attempt is computed
+    /// from the retry number, which is `0` for the first attempt after a successful connection.
+    /// This allows failing fast at first while giving a server that is slow to recover more time
+    pub fn connect_timeout_fn<F>(mut self, connect_timeout: F) -> Self
+    where
+        F: Fn(usize) -> Duration + Send + 'static,
+    {
+        self.connect_timeout = Some(Box::new(connect_timeout));
+        self
+    }
+
+    /// Set a timeout for receiving events on an open connection. When no event or comment arrives
+    /// within it, the connection is considered dead and an [`Error::IdleTimeout`] is yielded and
+    /// goes through the retry policy
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Set a timeout for receiving comments on an open connection, for servers that send
+    /// keep-alive comments regularly but events only sporadically. Unlike
+    /// [`EventSourceBuilder::idle_timeout`], events do not count, so when no comment arrives
+    /// within it an [`Error::HeartbeatTimeout`] is yielded and goes through the retry policy
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = Some(timeout);
+        self
+    }
+
+    /// Close the EventSource once `lifetime` elapsed since it was built, whether it is connected,
+    /// connecting or waiting to retry. It then yields an [`Event::Closed`] with
+    /// [`CloseReason::Expired`]. The deadline is not restarted by [`EventSource::reset`]
+    pub fn max_lifetime(mut self, lifetime: Duration) -> Self {
+        self.max_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Use `clock` for the delays and timestamps of the [`EventSource`] instead of the timer
+    /// backend, e.g. a mock clock in tests to check the retry delays without waiting
+    pub fn clock<C: Clock + Send + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Decide what to do with the status code of each response. By default only `200 OK` is
+    /// accepted and every other status is rejected
+    pub fn accept_status<F>(mut self, accept_status: F) -> Self
+    where
+        F: Fn(StatusCode) -> StatusAction + Send + 'static,
+    {
+        self.accept_status = Box::new(accept_status);
+        self
+    }
+
+    /// Decide whether to stream events from a response with the given `Content-Type`, which is
+    /// `None` if the header is missing or not a valid mime type. By default only
+    /// `text/event-stream` is accepted, in any case and with any parameters, and other types yield
+    /// an [`Error::InvalidContentType`], or an [`Error::MissingContentType`] if the header is
+    /// missing
+    pub fn accept_content_type<F>(mut self, accept_content_type: F) -> Self
+    where
+        F: Fn(Option<&mime::Mime>) -> bool + Send + 'static,
+    {
+        self.accept_content_type = Box::new(accept_content_type);
+        self
+    }
+
+    /// Call `on_reconnect` with the retry number and the delay that just elapsed whenever a
+    /// reconnect attempt starts. It runs while polling the stream, so it should be cheap and must
+    /// not block
+    pub fn on_reconnect<F>(mut self, on_reconnect: F) -> Self
+    where
+        F: FnMut(usize, Duration) + Send + 'static,
+    {
+        self.on_reconnect = Some(Box::new(on_reconnect));
+        self
+    }
+
+    /// Call `on_response` with each accepted response before its body is streamed, e.g. to read
+    /// its headers or extensions. It runs right before the [`Event::Open`] is yielded and cannot
+    /// consume the body
+    pub fn on_response<F>(mut self, on_response: F) -> Self
+    where
+        F: FnMut(&Response) + Send + 'static,
+    {
+        self.on_response = Some(Box::new(on_response));
+        self
+    }
+
+    /// Call `on_error` with each error right before it is yielded, e.g. to log reconnects apart
+    /// from the consumer of the events. It runs inside `poll_next`, so it must not block
+    pub fn on_error<F>(mut self, on_error: F) -> Self
+    where
+        F: FnMut(&Error) + Send + 'static,
+    {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+
+    /// Only reset the retry number and delay once a connection has stayed open for `window` and
+    /// then received an event, instead of as soon as it opens. This keeps backing off from a server
+    /// that accepts connections and drops them right away
+    pub fn reset_backoff_after(mut self, window: Duration) -> Self {
+        self.reset_backoff_after = Some(window);
+        self
+    }
+
+    /// Set whether reconnects are sent to the url a connection was redirected to, e.g. a node
+    /// specific streaming endpoint, instead of the original url. The headers of the original
+    /// request, such as `Authorization`, are then sent to that url directly. Disabled by default
+    pub fn follow_redirect_target(mut self, enabled: bool) -> Self {
+        self.follow_redirect_target = enabled;
+        self
+    }
+
+    /// Limit the number of bytes buffered for a single event, including its data and any partial
+    /// line. When a server sends more without completing the event, an [`Error::BufferOverflow`]
+    /// is yielded and goes through the retry policy. Unlimited by default
+    pub fn max_event_size(mut self, max_event_size: usize) -> Self {
+        self.max_event_size = Some(max_event_size);
+        self
+    }
+
+    /// Limit the number of bytes of a single line of the stream, i.e. a field name and its value,
+    /// such as one `data:` line. When a line grows beyond it, even before it is complete, an
+    /// [`Error::FieldTooLarge`] is yielded and goes through the retry policy. Unlimited by default
+    pub fn max_field_size(mut self, max_field_size: usize) -> Self {
+        self.max_field_size = Some(max_field_size);
+        self
+    }
+
+    /// Set whether the stream is parsed leniently, recovering from deviations from the spec that
+    /// some servers make instead of yielding an error. Field names are matched ignoring case and
+    /// surrounding whitespace, invalid UTF8 is replaced with U+FFFD, lines that cannot be parsed
+    /// are skipped and an event not terminated by a blank line is still dispatched when the
+    /// stream ends. Strict by default
+    pub fn lenient_parsing(mut self, enabled: bool) -> Self {
+        self.lenient_parsing = enabled;
+        self
+    }
+
+    /// Set whether a `retry` field that is not a valid integer yields an
+    /// [`Error::InvalidRetryField`], e.g. to log misbehaving servers. The error does not go
+    /// through the retry policy and the connection is kept. Such fields are silently ignored by
+    /// default
+    pub fn report_invalid_retry(mut self, enabled: bool) -> Self {
+        self.report_invalid_retry = enabled;
+        self
+    }
+
+    /// Set whether the data received for an event that is cut off, e.g. because the connection
+    /// dropped or timed out before the blank line ending it, is yielded as an
+    /// [`Error::PartialEvent`] right before the error or the end of the stream. The data is
+    /// discarded by default
+    pub fn report_partial_events(mut self, enabled: bool) -> Self {
+        self.report_partial_events = enabled;
+        self
+    }
+
+    /// Set whether comment lines, often sent by servers as keep-alives, are yielded as
+    /// [`Event::Comment`]s instead of being dropped, which is the default
+    pub fn emit_comments(mut self, enabled: bool) -> Self {
+        self.emit_comments = enabled;
+        self
+    }
+
+    /// Set whether an [`Event::Open`] is yielded for each connection, which is the default. When
+    /// disabled, the stream goes straight to the events of the connection, and the connection is
+    /// still reflected by [`EventSource::ready_state`]
+    pub fn emit_open(mut self, enabled: bool) -> Self {
+        self.emit_open = enabled;
+        self
+    }
+
+    /// Set the `Accept` header added to requests that do not already have one, which is
+    /// `text/event-stream` by default. `None` sends the requests without adding one. This also
+    /// applies to requests passed to [`EventSource::set_builder`] and [`EventSource::reset`]
+    pub fn accept(mut self, accept: Option<HeaderValue>) -> Self {
+        self.accept = accept;
+        self
+    }
+    /// Await `header_provider` before each connection attempt and add the headers it returns to
+    /// the request, replacing headers of the same name. Use this to send a fresh `Authorization`
+    /// header when tokens expire. The future is polled by the [`EventSource`] while connecting
+    pub fn header_provider<F>(mut self, header_provider: F) -> Self
+    where
+        F: Fn() -> HeaderFuture + Send + 'static,
+    {
+        self.header_provider = Some(Box::new(header_provider));
+        self
+    }
+
+    /// Regenerate the request body with `body_factory` for every connection attempt instead of
+    /// cloning it, so bodies that cannot be cloned such as streams can be sent. The body it
+    /// returns replaces one set with [`EventSourceBuilder::body`], which must then be left unset
+    /// for the request to be cloneable. It also applies to requests passed to
+    /// [`EventSource::set_builder`] and [`EventSource::reset`]
+    pub fn body_factory<F>(mut self, body_factory: F) -> Self
+    where
+        F: Fn() -> Body + Send + 'static,
+    {
+        self.body_factory = Some(Box::new(move || Ok(body_factory())));
+        self
+    }
+
+    /// Like [`EventSourceBuilder::body_factory`], but with a factory that can fail, e.g. when it
+    /// reads the body from a file. A failure is yielded as an [`Error::BodyFactory`] and goes
+    /// through the retry policy, so the factory is called again on the next attempt
+    pub fn try_body_factory<F, E>(mut self, body_factory: F) -> Self
+    where
+        F: Fn() -> Result<Body, E> + Send + 'static,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        self.body_factory = Some(Box::new(move || body_factory().map_err(Into::into)));
+        self
+    }
+
+    /// Pass the request of each connection attempt through `before_request` right before it is
+    /// sent, e.g. to stamp a trace id or the attempt number. It receives a clone of the request
+    /// with the `Last-Event-ID` and [`EventSourceBuilder::header_provider`] headers applied, and
+    /// runs while polling the stream, so it should be cheap and must not block
+    pub fn before_request<F>(mut self, before_request: F) -> Self
+    where
+        F: FnMut(RequestBuilder) -> RequestBuilder + Send + 'static,
+    {
+        self.before_request = Some(Box::new(before_request));
+        self
+    }
+}
+
+impl<R: RetryPolicy> EventSourceBuilder<R> {
+    /// Build the [`EventSource`]
+    pub fn build(self) -> Result<EventSource<R>, CannotCloneRequestError> {
+        EventSource::from_builder(self)
+    }
+}
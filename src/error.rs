@@ -1,32 +1,86 @@
+use crate::timer::SystemTime;
 use core::fmt;
+use core::ops::Deref;
 use eventsource_stream::EventStreamError;
 use nom::error::Error as NomError;
-use reqwest::header::HeaderValue;
+use reqwest::header::{HeaderValue, RETRY_AFTER};
 use reqwest::Error as ReqwestError;
 use reqwest::Response;
 use reqwest::StatusCode;
 use std::string::FromUtf8Error;
+use std::sync::Arc;
+use std::time::Duration;
 
+#[cfg(all(doc, feature = "event-source"))]
+use crate::builder::EventSourceBuilder;
+#[cfg(doc)]
+use crate::event_stream::EventStream;
 #[cfg(doc)]
 use reqwest::RequestBuilder;
 
-/// Error raised when a [`RequestBuilder`] cannot be cloned. See [`RequestBuilder::try_clone`] for
-/// more information
-#[derive(Debug, Clone, Copy)]
-pub struct CannotCloneRequestError;
+/// Error raised when a [`RequestBuilder`] cannot be cloned, either because its body is a stream or
+/// because the request is invalid (e.g. a bad url). See [`RequestBuilder::try_clone`] for more
+/// information.
+#[cfg_attr(
+    feature = "event-source",
+    doc = "A streaming body can instead be regenerated for every connection with \
+           [`EventSourceBuilder::body_factory`]"
+)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CannotCloneRequestError {
+    streaming_body: bool,
+}
+
+impl CannotCloneRequestError {
+    #[cfg(feature = "event-source")]
+    pub(crate) fn invalid_request() -> Self {
+        Self {
+            streaming_body: false,
+        }
+    }
+
+    #[cfg(feature = "event-source")]
+    pub(crate) fn streaming_body() -> Self {
+        Self {
+            streaming_body: true,
+        }
+    }
+
+    /// Whether the request has a body that is a stream, which cannot be cloned
+    pub fn is_streaming_body(&self) -> bool {
+        self.streaming_body
+    }
+
+    /// Whether the request could not be built, e.g. because of a bad url or header
+    pub fn is_invalid_request(&self) -> bool {
+        !self.streaming_body
+    }
+}
 
 impl fmt::Display for CannotCloneRequestError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("expected a cloneable request")
+        if self.streaming_body {
+            f.write_str(
+                "expected a cloneable request, but its body is a stream; use a body factory to \
+                 create the body for every connection instead",
+            )
+        } else {
+            f.write_str("expected a cloneable request, but the request is invalid (e.g. a bad url)")
+        }
     }
 }
 
 impl std::error::Error for CannotCloneRequestError {}
 
-/// Error raised by the EventSource stream fetching and parsing
+/// Error raised by the EventSource stream fetching and parsing.
+///
+/// The enum is `#[non_exhaustive]`, since features such as `json` add variants and new errors may
+/// be reported in the future, so a `match` on it needs a wildcard arm
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
-    /// Source stream is not valid UTF8
+    /// Source stream is not valid UTF8. This is reported separately from [`Error::Parser`] and
+    /// [`Error::Transport`], so a retry policy can give up on a server sending a bad encoding
     #[error(transparent)]
     Utf8(FromUtf8Error),
     /// Source stream is not a valid EventStream
@@ -38,6 +92,13 @@ pub enum Error {
     /// The `Content-Type` returned by the server is invalid
     #[error("Invalid header value: {0:?}")]
     InvalidContentType(HeaderValue, Response),
+    /// The server did not return a `Content-Type` header
+    #[error("Missing content type")]
+    MissingContentType(Response),
+    /// The server compressed the stream with a `Content-Encoding` that is not decompressed. Enable
+    /// the `gzip`, `brotli`, `deflate` or `zstd` feature matching it
+    #[error("Unsupported content encoding: {0:?}")]
+    UnsupportedContentEncoding(HeaderValue, Response),
     /// The status code returned by the server is invalid
     #[error("Invalid status code: {0}")]
     InvalidStatusCode(StatusCode, Response),
@@ -47,6 +108,149 @@ pub enum Error {
     /// The stream ended
     #[error("Stream ended")]
     StreamEnded,
+    /// The server did not respond within the configured connection timeout
+    #[error("Connection timed out")]
+    ConnectTimeout,
+    /// No event was received within the configured idle timeout
+    #[error("Connection idle timed out")]
+    IdleTimeout,
+    /// No comment was received within the configured heartbeat timeout
+    #[error("Connection heartbeat timed out")]
+    HeartbeatTimeout,
+    /// A single event exceeded the configured maximum event size
+    #[error("Event exceeded the maximum event size")]
+    BufferOverflow,
+    /// A single line of the stream exceeded the configured maximum field size
+    #[error("Field exceeded the maximum field size")]
+    FieldTooLarge,
+    /// The server sent a `retry` field that is not a valid integer. Only yielded when enabled
+    /// with [`EventStream::set_report_invalid_retry`], and does not close the connection
+    #[cfg_attr(
+        feature = "event-source",
+        doc = "\nAn `EventSource` yields it when enabled with \
+               [`EventSourceBuilder::report_invalid_retry`]"
+    )]
+    #[error("Invalid `retry` field: {0:?}")]
+    InvalidRetryField(String),
+    /// The data of an event that was cut off by an error or the end of the stream, without a
+    /// line that was still incomplete. Only yielded when enabled with
+    /// [`EventStream::set_report_partial_events`], right before that error or the end, and does
+    /// not go through the retry policy itself
+    #[cfg_attr(
+        feature = "event-source",
+        doc = "\nAn `EventSource` yields it when enabled with \
+               [`EventSourceBuilder::report_partial_events`]"
+    )]
+    #[error("Partial event: {0:?}")]
+    PartialEvent(String),
+    /// The body factory of an `EventSource` failed to create the request body. This goes through
+    /// the retry policy like a failed request
+    #[cfg_attr(
+        feature = "event-source",
+        doc = "\nSee [`EventSourceBuilder::try_body_factory`]"
+    )]
+    #[error("Failed to create the request body: {0}")]
+    BodyFactory(Box<dyn std::error::Error + Send + Sync>),
+    /// The data of a message could not be deserialized. Requires the `json` feature
+    #[cfg(feature = "json")]
+    #[error("Failed to deserialize event data: {0}")]
+    Deserialize(serde_json::Error),
+}
+
+impl Error {
+    /// Get the delay the server asked for in the `Retry-After` header of a `429 Too Many Requests`
+    /// or `503 Service Unavailable` response, if any. Both the delay-seconds and HTTP-date forms
+    /// are supported, and the delay is capped at one day. An HTTP-date is compared with the system
+    /// time rather than a [`Clock`](crate::Clock), since it is a wall-clock time
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::InvalidStatusCode(
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE,
+                response,
+            ) => parse_retry_after(response.headers().get(RETRY_AFTER)?),
+            _ => None,
+        }
+    }
+
+    /// Whether the built-in retry policies retry after this error. Transport errors are retried
+    /// unless the request could not be built (e.g. an invalid url) or followed too many redirects,
+    /// since retrying those would fail the same way. Server errors and `429 Too Many Requests`
+    /// are retried, other rejected status codes are not. Use it in a custom
+    /// [`RetryPolicy`](crate::retry::RetryPolicy) to classify errors the same way.
+    ///
+    /// TLS failures, including an invalid or untrusted certificate, are retried as well. reqwest
+    /// reports them as connection errors that cannot be told apart without the types of the TLS
+    /// backend it was built with, which this crate does not depend on. To give up on them, use a
+    /// custom policy that walks [`std::error::Error::source`] of an [`Error::Transport`] for the
+    /// certificate error of that backend
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Transport(err) => !(err.is_builder() || err.is_redirect()),
+            Self::InvalidStatusCode(status, _) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::InvalidContentType(..)
+            | Self::MissingContentType(_)
+            | Self::UnsupportedContentEncoding(..)
+            | Self::InvalidLastEventId(_) => false,
+            #[cfg(feature = "json")]
+            Self::Deserialize(_) => false,
+            _ => true,
+        }
+    }
+}
+
+/// An [`enum@Error`] that can be cloned by sharing it, e.g. to broadcast the items of an
+/// `EventSource` to several consumers. It dereferences to the [`enum@Error`] and is created
+/// with `From`
+#[derive(Debug, Clone)]
+pub struct CloneableError(Arc<Error>);
+
+impl From<Error> for CloneableError {
+    // the response is not `Send` on wasm, where sharing across threads does not apply
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn from(err: Error) -> Self {
+        Self(Arc::new(err))
+    }
+}
+
+impl Deref for CloneableError {
+    type Target = Error;
+
+    fn deref(&self) -> &Error {
+        &self.0
+    }
+}
+
+impl fmt::Display for CloneableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for CloneableError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// The longest `Retry-After` delay honored, so a server cannot stall a client indefinitely or
+/// overflow the timers with a huge value
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn parse_retry_after(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER));
+    }
+    let date = httpdate::parse_http_date(value)
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+    Some(date.saturating_sub(now).min(MAX_RETRY_AFTER))
 }
 
 impl From<EventStreamError<ReqwestError>> for Error {
@@ -0,0 +1,129 @@
+//! The items yielded by an [`EventSource`], which do not depend on it and are also yielded by
+//! the [`EventStream`](crate::EventStream) parser
+
+use reqwest::header::HeaderMap;
+use reqwest::{StatusCode, Version};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub use eventsource_stream::Event as MessageEvent;
+
+#[cfg(all(doc, feature = "event-source"))]
+use crate::{builder::EventSourceBuilder, event_source::EventSource};
+
+/// Details of the response an `EventSource` connected with, carried by [`Event::Open`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OpenEvent {
+    /// The status code of the response
+    pub status: StatusCode,
+    /// The headers of the response
+    pub headers: HeaderMap,
+    /// The HTTP version negotiated for the response, e.g. to check whether the stream shares an
+    /// HTTP/2 connection with others. The browser does not expose it on wasm, where it is `None`
+    pub version: Option<Version>,
+    /// The address of the server that sent the response, e.g. to tell which replica behind a load
+    /// balancer serves the stream. It is `None` if reqwest does not know it and always on wasm
+    pub remote_addr: Option<SocketAddr>,
+    /// Whether the EventSource was connected before, so events may have been missed or repeated
+    /// since then
+    pub reconnect: bool,
+    /// The number of the retry which opened this connection, or `0` if no retry was needed
+    pub retry_num: usize,
+    /// How long it took from sending the request to receiving the response headers
+    pub connect_duration: Duration,
+}
+
+/// Events created by the `EventSource`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Event {
+    /// The event fired when the connection is opened
+    Open(OpenEvent),
+    /// The event fired when a [`MessageEvent`] is received
+    Message(MessageEvent),
+    /// The event fired when a comment line (starting with `:`) is received, without the colon
+    /// and a single leading space.
+    #[cfg_attr(
+        feature = "event-source",
+        doc = "Only emitted by an `EventSource` when enabled with \
+               [`EventSourceBuilder::emit_comments`]"
+    )]
+    Comment(String),
+    /// The event fired once when the EventSource closes, either through `EventSource::close` or
+    /// because the retry policy gave up. The stream ends after this event
+    Closed,
+}
+
+impl Event {
+    /// Get the `event` field of an [`Event::Message`], or `None` for other events
+    pub fn event_type(&self) -> Option<&str> {
+        match self {
+            Self::Message(message) => Some(&message.event),
+            _ => None,
+        }
+    }
+
+    /// Get the data of an [`Event::Message`], or `None` for other events
+    pub fn data(&self) -> Option<&str> {
+        match self {
+            Self::Message(message) => Some(&message.data),
+            _ => None,
+        }
+    }
+
+    /// Get the message of an [`Event::Message`], or `None` for other events
+    pub fn as_message(&self) -> Option<&MessageEvent> {
+        match self {
+            Self::Message(message) => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Turn an [`Event::Message`] into its message, or `None` for other events
+    pub fn into_message(self) -> Option<MessageEvent> {
+        match self {
+            Self::Message(message) => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Compare two events ignoring the `id` and `retry` fields of messages, so messages are equal
+    /// if their `event` type and data are. Other events are compared as with `==`
+    pub fn data_eq(&self, other: &Event) -> bool {
+        match (self, other) {
+            (Self::Message(a), Self::Message(b)) => a.event == b.event && a.data == b.data,
+            _ => self == other,
+        }
+    }
+}
+
+impl From<MessageEvent> for Event {
+    fn from(event: MessageEvent) -> Self {
+        Event::Message(event)
+    }
+}
+
+/// A message with its fields flattened into owned values, independent of the [`MessageEvent`]
+/// type.
+#[cfg_attr(feature = "event-source", doc = "See [`EventSource::messages_simple`]")]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SimpleEvent {
+    /// The id of the message, or the last id received if the message has none
+    pub id: String,
+    /// The event type, which is `message` if the server did not set one
+    pub event: String,
+    /// The data of the message
+    pub data: String,
+    /// The reconnection time the server asked for with the message, if any
+    pub retry: Option<Duration>,
+}
+
+impl From<MessageEvent> for SimpleEvent {
+    fn from(message: MessageEvent) -> Self {
+        Self {
+            id: message.id,
+            event: message.event,
+            data: message.data,
+            retry: message.retry,
+        }
+    }
+}
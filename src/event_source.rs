@@ -1,23 +1,34 @@
+use crate::builder::EventSourceBuilder;
 use crate::error::{CannotCloneRequestError, Error};
-use crate::retry::{RetryPolicy, DEFAULT_RETRY};
+use crate::event::{Event, MessageEvent, OpenEvent};
+use crate::event_stream::EventStream;
+use crate::retry::{Never, RetryPolicy};
+use crate::telemetry;
+use crate::timer::{ClockHandle, Instant, Timeout};
+use core::fmt;
+use core::future::poll_fn;
 use core::pin::Pin;
-use eventsource_stream::Eventsource;
-pub use eventsource_stream::{Event as MessageEvent, EventStreamError};
+use core::task::{RawWaker, RawWakerVTable, Waker};
 #[cfg(not(target_arch = "wasm32"))]
 use futures_core::future::BoxFuture;
 use futures_core::future::Future;
 #[cfg(target_arch = "wasm32")]
 use futures_core::future::LocalBoxFuture;
+use futures_core::ready;
 #[cfg(not(target_arch = "wasm32"))]
 use futures_core::stream::BoxStream;
 #[cfg(target_arch = "wasm32")]
 use futures_core::stream::LocalBoxStream;
-use futures_core::stream::Stream;
+use futures_core::stream::{FusedStream, Stream};
 use futures_core::task::{Context, Poll};
-use futures_timer::Delay;
 use pin_project_lite::pin_project;
-use reqwest::header::{HeaderName, HeaderValue};
-use reqwest::{Error as ReqwestError, IntoUrl, RequestBuilder, Response, StatusCode};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue};
+use reqwest::{
+    Body, Client, Error as ReqwestError, IntoUrl, Method, RequestBuilder, Response, StatusCode,
+    Url, Version,
+};
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::time::Duration;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -26,11 +37,58 @@ type ResponseFuture = BoxFuture<'static, Result<Response, ReqwestError>>;
 type ResponseFuture = LocalBoxFuture<'static, Result<Response, ReqwestError>>;
 
 #[cfg(not(target_arch = "wasm32"))]
-type EventStream = BoxStream<'static, Result<MessageEvent, EventStreamError<ReqwestError>>>;
+pub(crate) type HeaderFuture = BoxFuture<'static, HeaderMap>;
 #[cfg(target_arch = "wasm32")]
-type EventStream = LocalBoxStream<'static, Result<MessageEvent, EventStreamError<ReqwestError>>>;
+pub(crate) type HeaderFuture = LocalBoxFuture<'static, HeaderMap>;
 
-type BoxedRetry = Box<dyn RetryPolicy + Send + Unpin + 'static>;
+#[cfg(not(target_arch = "wasm32"))]
+type ByteStream = BoxStream<'static, Result<bytes::Bytes, ReqwestError>>;
+#[cfg(target_arch = "wasm32")]
+type ByteStream = LocalBoxStream<'static, Result<bytes::Bytes, ReqwestError>>;
+
+pub(crate) type BoxedRetry = Box<dyn RetryPolicy + Send + Unpin + 'static>;
+pub(crate) type BoxedAcceptStatus = Box<dyn Fn(StatusCode) -> StatusAction + Send + 'static>;
+type AcceptContentType = dyn Fn(Option<&mime::Mime>) -> bool + Send + 'static;
+pub(crate) type BoxedAcceptContentType = Box<AcceptContentType>;
+pub(crate) type BoxedConnectTimeout = Box<dyn Fn(usize) -> Duration + Send + 'static>;
+pub(crate) type BoxedOnReconnect = Box<dyn FnMut(usize, Duration) + Send + 'static>;
+pub(crate) type BoxedOnResponse = Box<dyn FnMut(&Response) + Send + 'static>;
+pub(crate) type BoxedOnError = Box<dyn FnMut(&Error) + Send + 'static>;
+pub(crate) type BoxedHeaderProvider = Box<dyn Fn() -> HeaderFuture + Send + 'static>;
+pub(crate) type BoxedBodyFactory =
+    Box<dyn Fn() -> Result<Body, Box<dyn std::error::Error + Send + Sync>> + Send + 'static>;
+type BoxedMapEvent = Box<dyn FnMut(Event) -> Event + Send + 'static>;
+pub(crate) type BoxedBeforeRequest =
+    Box<dyn FnMut(RequestBuilder) -> RequestBuilder + Send + 'static>;
+
+// `pin_project!` does not support `#[cfg]` on fields, so the sender is a unit without the feature
+#[cfg(feature = "watch")]
+type ReadyStateSender = tokio::sync::watch::Sender<ReadyState>;
+#[cfg(not(feature = "watch"))]
+type ReadyStateSender = ();
+
+#[cfg(feature = "watch")]
+fn ready_state_sender() -> ReadyStateSender {
+    tokio::sync::watch::channel(ReadyState::Connecting).0
+}
+#[cfg(not(feature = "watch"))]
+fn ready_state_sender() -> ReadyStateSender {}
+
+/// Which [`Event::Message`]s an [`EventSource`] yields, by their `event` field
+#[derive(Debug)]
+enum EventFilter {
+    Only(HashSet<String>),
+    Exclude(HashSet<String>),
+}
+
+impl EventFilter {
+    fn matches(&self, event: &MessageEvent) -> bool {
+        match self {
+            Self::Only(types) => types.contains(&event.event),
+            Self::Exclude(types) => !types.contains(&event.event),
+        }
+    }
+}
 
 /// The ready state of an [`EventSource`]
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
@@ -44,242 +102,1432 @@ pub enum ReadyState {
     Closed = 2,
 }
 
+/// Why an [`EventSource`] closed, see [`EventSource::close_reason`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CloseReason {
+    /// [`EventSource::close`] or [`EventSource::close_graceful`] was called
+    UserClosed,
+    /// The retry policy gave up after an error that is retryable, see [`Error::is_retryable`]
+    RetriesExhausted,
+    /// The server ended the stream, either with the stream end not treated as an error or with a
+    /// status mapped to [`StatusAction::Close`]
+    StreamFinished,
+    /// An error which would fail the same way again occurred, e.g. an invalid content type, or the
+    /// retry policy gave up after a non-retryable error such as a `401 Unauthorized` status
+    Fatal,
+    /// The lifetime set with [`EventSourceBuilder::max_lifetime`] elapsed
+    Expired,
+}
+
+/// What an [`EventSource`] does with the status code of a response
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StatusAction {
+    /// Stream events from the response
+    Accept,
+    /// Close the EventSource cleanly with an [`Event::Closed`], e.g. for `204 No Content`
+    Close,
+    /// Yield an [`Error::InvalidStatusCode`], which goes through the retry policy
+    Reject,
+}
+
+pub(crate) fn default_accept_content_type(mime_type: Option<&mime::Mime>) -> bool {
+    matches!(
+        mime_type.map(|mime_type| (mime_type.type_(), mime_type.subtype())),
+        Some((mime::TEXT, mime::EVENT_STREAM))
+    )
+}
+
+pub(crate) fn default_accept_status(status: StatusCode) -> StatusAction {
+    if status == StatusCode::OK {
+        StatusAction::Accept
+    } else {
+        StatusAction::Reject
+    }
+}
+
 pin_project! {
 /// Provides the [`Stream`] implementation for the [`Event`] items. This wraps the
 /// [`RequestBuilder`] and retries requests when they fail.
+///
+/// The retry policy is stored inline as `R`, which defaults to a boxed [`RetryPolicy`]. Use
+/// [`EventSourceBuilder::retry_policy_inline`] to pick a concrete policy and avoid the box.
+///
+/// The EventSource is [`Unpin`], so `&mut EventSource` is a [`Stream`] as well. It can be polled
+/// through a mutable borrow, e.g. with `StreamExt::next` or in a `select!` loop, and still be used
+/// in between to call [`EventSource::ready_state`] or [`EventSource::close`].
 #[project = EventSourceProjection]
-pub struct EventSource {
+pub struct EventSource<R = BoxedRetry> {
     builder: RequestBuilder,
     #[pin]
+    next_headers: Option<HeaderFuture>,
+    #[pin]
     next_response: Option<ResponseFuture>,
     #[pin]
-    cur_stream: Option<EventStream>,
+    cur_stream: Option<EventStream<ByteStream>>,
+    #[pin]
+    delay: Option<Timeout>,
     #[pin]
-    delay: Option<Delay>,
+    connect_delay: Option<Timeout>,
+    #[pin]
+    idle_delay: Option<Timeout>,
+    #[pin]
+    heartbeat_delay: Option<Timeout>,
+    #[pin]
+    lifetime_delay: Option<Timeout>,
     is_closed: bool,
-    retry_policy: BoxedRetry,
+    closed_emitted: bool,
+    draining: bool,
+    paused: bool,
+    resume_waker: Option<Waker>,
+    retry_policy: R,
     last_event_id: String,
-    last_retry: Option<(usize, Duration)>
+    last_event_id_bytes: Option<HeaderValue>,
+    last_retry: Option<(usize, Duration)>,
+    treat_stream_end_as_error: bool,
+    connect_timeout: Option<BoxedConnectTimeout>,
+    idle_timeout: Option<Duration>,
+    heartbeat_timeout: Option<Duration>,
+    accept_status: BoxedAcceptStatus,
+    accept_content_type: BoxedAcceptContentType,
+    on_reconnect: Option<BoxedOnReconnect>,
+    on_response: Option<BoxedOnResponse>,
+    on_error: Option<BoxedOnError>,
+    reset_backoff_after: Option<Duration>,
+    follow_redirect_target: bool,
+    opened_at: Option<Instant>,
+    reconnect_at: Option<Instant>,
+    fetched_at: Option<Instant>,
+    clock: ClockHandle,
+    max_event_size: Option<usize>,
+    max_field_size: Option<usize>,
+    lenient_parsing: bool,
+    report_invalid_retry: bool,
+    report_partial_events: bool,
+    emit_comments: bool,
+    emit_open: bool,
+    event_filter: Option<EventFilter>,
+    map_event: Option<BoxedMapEvent>,
+    stats: Stats,
+    accept: Option<HeaderValue>,
+    header_provider: Option<BoxedHeaderProvider>,
+    body_factory: Option<BoxedBodyFactory>,
+    before_request: Option<BoxedBeforeRequest>,
+    close_reason: Option<CloseReason>,
+    pending_error: Option<Error>,
+    ready_state_tx: ReadyStateSender,
 }
 }
 
 impl EventSource {
     /// Wrap a [`RequestBuilder`]
     pub fn new(builder: RequestBuilder) -> Result<Self, CannotCloneRequestError> {
-        let builder = builder.header(
-            reqwest::header::ACCEPT,
-            HeaderValue::from_static("text/event-stream"),
-        );
-        let res_future = Box::pin(builder.try_clone().ok_or(CannotCloneRequestError)?.send());
+        EventSourceBuilder::new(builder).build()
+    }
+
+    /// Start configuring an EventSource for a [`RequestBuilder`], like
+    /// [`EventSourceBuilder::new`]
+    pub fn builder(builder: RequestBuilder) -> EventSourceBuilder {
+        EventSourceBuilder::new(builder)
+    }
+
+    /// Create a simple EventSource based on a GET request. Fails if the url is invalid. Its client
+    /// does not store cookies, see [the crate docs](crate#cookies)
+    pub fn get<T: IntoUrl>(url: T) -> Result<Self, CannotCloneRequestError> {
+        Self::new(reqwest::Client::new().get(url))
+    }
+
+    /// Create a simple EventSource based on a POST request without a body. Use an
+    /// [`EventSourceBuilder`] to set a body or headers. Fails if the url is invalid. Its client
+    /// does not store cookies, see [the crate docs](crate#cookies)
+    pub fn post<T: IntoUrl>(url: T) -> Result<Self, CannotCloneRequestError> {
+        Self::new(reqwest::Client::new().post(url))
+    }
+
+    /// Create a simple EventSource based on a GET request sent with an existing [`Client`], to
+    /// share its connection pool and configuration, such as a cookie store. Fails if the url is
+    /// invalid
+    pub fn get_with_client<T: IntoUrl>(
+        client: &Client,
+        url: T,
+    ) -> Result<Self, CannotCloneRequestError> {
+        Self::new(client.get(url))
+    }
+
+    /// Create a simple EventSource based on a POST request without a body sent with an existing
+    /// [`Client`]. Fails if the url is invalid
+    pub fn post_with_client<T: IntoUrl>(
+        client: &Client,
+        url: T,
+    ) -> Result<Self, CannotCloneRequestError> {
+        Self::new(client.post(url))
+    }
+
+    /// Create an EventSource whose request body is regenerated by `body_factory` for every
+    /// connection attempt instead of being cloned, for bodies that cannot be cloned such as
+    /// streams. Fails if the url is invalid
+    pub fn new_with_body_factory<T, F>(
+        method: Method,
+        url: T,
+        body_factory: F,
+    ) -> Result<Self, CannotCloneRequestError>
+    where
+        T: IntoUrl,
+        F: Fn() -> Body + Send + 'static,
+    {
+        EventSourceBuilder::request(method, url)
+            .body_factory(body_factory)
+            .build()
+    }
+
+    /// Create an EventSource sent with an existing [`Client`] whose request body is regenerated by
+    /// `body_factory` for every connection attempt, so many streams with different bodies share
+    /// one connection pool. Fails if the url is invalid
+    pub fn new_with_client_and_body_factory<T, F>(
+        client: &Client,
+        method: Method,
+        url: T,
+        body_factory: F,
+    ) -> Result<Self, CannotCloneRequestError>
+    where
+        T: IntoUrl,
+        F: Fn() -> Body + Send + 'static,
+    {
+        EventSourceBuilder::new(client.request(method, url))
+            .body_factory(body_factory)
+            .build()
+    }
+
+    /// Close on the first error instead of reconnecting, by using the [`Never`] retry policy
+    pub fn without_retries(self) -> Self {
+        self.with_retry_policy(Box::new(Never))
+    }
+}
+
+impl<R: RetryPolicy> EventSource<R> {
+    pub(crate) fn from_builder(
+        EventSourceBuilder {
+            builder,
+            retry_policy,
+            last_event_id,
+            treat_stream_end_as_error,
+            connect_timeout,
+            idle_timeout,
+            heartbeat_timeout,
+            max_lifetime,
+            accept_status,
+            accept_content_type,
+            on_reconnect,
+            on_response,
+            on_error,
+            reset_backoff_after,
+            follow_redirect_target,
+            max_event_size,
+            max_field_size,
+            lenient_parsing,
+            report_invalid_retry,
+            report_partial_events,
+            emit_comments,
+            emit_open,
+            accept,
+            header_provider,
+            body_factory,
+            before_request,
+            clock,
+        }: EventSourceBuilder<R>,
+    ) -> Result<Self, CannotCloneRequestError> {
+        let builder = prepare_builder(builder, accept.as_ref())?;
+        let clock = ClockHandle::new(clock);
+        // the first request is sent when the stream is first polled, in the same way as reconnects
         Ok(Self {
             builder,
-            next_response: Some(res_future),
+            next_headers: None,
+            next_response: None,
             cur_stream: None,
             delay: None,
+            connect_delay: None,
+            idle_delay: None,
+            heartbeat_delay: None,
+            lifetime_delay: max_lifetime.map(|lifetime| clock.delay(lifetime)),
             is_closed: false,
-            retry_policy: Box::new(DEFAULT_RETRY),
-            last_event_id: String::new(),
+            closed_emitted: false,
+            draining: false,
+            paused: false,
+            resume_waker: None,
+            retry_policy,
+            last_event_id,
+            last_event_id_bytes: None,
             last_retry: None,
+            treat_stream_end_as_error,
+            connect_timeout,
+            idle_timeout,
+            heartbeat_timeout,
+            accept_status,
+            accept_content_type,
+            on_reconnect,
+            on_response,
+            on_error,
+            reset_backoff_after,
+            follow_redirect_target,
+            opened_at: None,
+            reconnect_at: None,
+            fetched_at: None,
+            clock,
+            max_event_size,
+            max_field_size,
+            lenient_parsing,
+            report_invalid_retry,
+            report_partial_events,
+            emit_comments,
+            emit_open,
+            event_filter: None,
+            map_event: None,
+            stats: Stats::default(),
+            accept,
+            header_provider,
+            body_factory,
+            before_request,
+            close_reason: None,
+            pending_error: None,
+            ready_state_tx: ready_state_sender(),
         })
     }
 
-    /// Create a simple EventSource based on a GET request
-    pub fn get<T: IntoUrl>(url: T) -> Self {
-        Self::new(reqwest::Client::new().get(url)).unwrap()
+    /// Replace the [`RequestBuilder`] used for the next reconnect, e.g. to refresh an
+    /// `Authorization` header. The current connection is kept open
+    pub fn set_builder(&mut self, builder: RequestBuilder) -> Result<(), CannotCloneRequestError> {
+        self.builder = prepare_builder(builder, self.accept.as_ref())?;
+        Ok(())
+    }
+
+    /// Point the EventSource at a new request, e.g. to fail over to another replica, keeping the
+    /// retry policy and configuration. The current connection and retry state are dropped, a
+    /// closed EventSource is reopened, and the new request is sent the next time the stream is
+    /// polled. The last event id is kept so the new endpoint can resume the stream
+    pub fn reset(&mut self, builder: RequestBuilder) -> Result<(), CannotCloneRequestError> {
+        self.builder = prepare_builder(builder, self.accept.as_ref())?;
+        self.next_headers = None;
+        self.next_response = None;
+        self.cur_stream = None;
+        self.delay = None;
+        self.connect_delay = None;
+        self.idle_delay = None;
+        self.heartbeat_delay = None;
+        self.is_closed = false;
+        self.close_reason = None;
+        self.pending_error = None;
+        self.closed_emitted = false;
+        self.draining = false;
+        self.last_retry = None;
+        self.opened_at = None;
+        self.stats.connected_at = None;
+        #[cfg(feature = "watch")]
+        self.notify_ready_state();
+        Ok(())
     }
 
     /// Close the EventSource stream and stop trying to reconnect
     pub fn close(&mut self) {
         self.is_closed = true;
+        self.close_reason.get_or_insert(CloseReason::UserClosed);
+        #[cfg(feature = "watch")]
+        self.notify_ready_state();
+    }
+
+    /// Stop driving the connection until [`EventSource::resume`] is called, so a slow consumer
+    /// applies backpressure to the server instead of events being buffered. While paused, the
+    /// stream is pending, except to yield an [`Event::Closed`] when closed
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resume a paused EventSource, waking the task waiting on it
+    pub fn resume(&mut self) {
+        self.paused = false;
+        if let Some(waker) = self.resume_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Whether the EventSource is paused, see [`EventSource::pause`]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stop reconnecting, but keep yielding the events of the current connection until it ends,
+    /// then close. If not connected, this behaves like [`EventSource::close`]
+    pub fn close_graceful(&mut self) {
+        if self.cur_stream.is_none() {
+            self.close();
+        } else {
+            self.draining = true;
+        }
+    }
+
+    /// Drop the current connection or pending retry delay and reconnect the next time the stream
+    /// is polled. Does nothing if the EventSource is closed or closing gracefully
+    pub fn reconnect(&mut self) {
+        if self.is_closed || self.draining {
+            return;
+        }
+        self.delay = None;
+        self.next_headers = None;
+        self.next_response = None;
+        self.cur_stream = None;
+        self.stats.connected_at = None;
+        self.stats.reconnects += 1;
+        telemetry::reconnect();
+        #[cfg(feature = "watch")]
+        self.notify_ready_state();
+    }
+
+    /// Set the retry policy. This also resets the retry number and delay
+    pub fn set_retry_policy(&mut self, policy: R) -> &mut Self {
+        self.retry_policy = policy;
+        self.last_retry = None;
+        self
+    }
+
+    /// Set the retry policy, returning the EventSource for chaining
+    pub fn with_retry_policy(mut self, policy: R) -> Self {
+        self.set_retry_policy(policy);
+        self
+    }
+
+    /// Get the current retry policy
+    pub fn retry_policy(&self) -> &R {
+        &self.retry_policy
+    }
+
+    /// Get a mutable reference to the current retry policy, e.g. to change the delay of a
+    /// [`Constant`](crate::retry::Constant) policy with `set_delay` while the EventSource runs.
+    /// Unlike [`EventSource::set_retry_policy`], the retry state is kept
+    pub fn retry_policy_mut(&mut self) -> &mut R {
+        &mut self.retry_policy
+    }
+
+    /// Get the number and delay of the last retry since the connection was last opened, if any
+    pub fn last_retry(&self) -> Option<(usize, Duration)> {
+        self.last_retry
     }
 
-    /// Set the retry policy
-    pub fn set_retry_policy(&mut self, policy: BoxedRetry) {
-        self.retry_policy = policy
+    /// Get the time left until the next reconnect attempt if one is scheduled after an error, or
+    /// `None` if the EventSource is connected, connecting right now or closed
+    pub fn time_until_reconnect(&self) -> Option<Duration> {
+        if self.is_closed {
+            return None;
+        }
+        self.delay.as_ref()?;
+        self.reconnect_at
+            .map(|reconnect_at| reconnect_at.saturating_duration_since(self.clock.now()))
     }
 
-    /// Get the last event id
+    /// Get the last event id, or an empty string if the server has not sent one. This can be
+    /// persisted to resume the stream later
     pub fn last_event_id(&self) -> &str {
         &self.last_event_id
     }
 
+    /// Get the last event id as the bytes sent in the `Last-Event-ID` header, which differ from
+    /// [`EventSource::last_event_id`] only for an id set with
+    /// [`EventSource::set_last_event_id_bytes`] that is not valid UTF8
+    pub fn last_event_id_bytes(&self) -> &[u8] {
+        match &self.last_event_id_bytes {
+            Some(id) => id.as_bytes(),
+            None => self.last_event_id.as_bytes(),
+        }
+    }
+
+    /// Set the last event id to be sent in the `Last-Event-ID` header of the next request. Use
+    /// this to resume a stream from a persisted [`EventSource::last_event_id`]. A request that is
+    /// already in flight is restarted so it carries the new id. An id that is not a valid header
+    /// value, e.g. one containing a CR or LF, fails the next request with an
+    /// [`Error::InvalidLastEventId`]
+    pub fn set_last_event_id(&mut self, id: impl Into<String>) {
+        self.last_event_id = id.into();
+        self.last_event_id_bytes = None;
+        // events of the open connection without an `id` field inherit the new id
+        if let Some(stream) = self.cur_stream.as_mut() {
+            stream.set_last_event_id(self.last_event_id.clone());
+        }
+        self.next_headers = None;
+        self.next_response = None;
+    }
+
+    /// Like [`EventSource::set_last_event_id`], but with an id of arbitrary bytes that is sent
+    /// unchanged, e.g. an opaque binary token. Fails if the id cannot be sent in a header, i.e. it
+    /// contains a CR, LF, NUL or another control character, leaving the last event id unchanged.
+    /// [`EventSource::last_event_id`] is then the id decoded lossily as UTF8, until the server
+    /// sends another id
+    pub fn set_last_event_id_bytes(
+        &mut self,
+        id: impl AsRef<[u8]>,
+    ) -> Result<(), InvalidHeaderValue> {
+        let id = id.as_ref();
+        let header = HeaderValue::from_bytes(id)?;
+        self.set_last_event_id(String::from_utf8_lossy(id));
+        self.last_event_id_bytes = Some(header);
+        Ok(())
+    }
+
+    /// Pass every event through `map_event` before yielding it, e.g. to strip a prefix from the
+    /// data of messages. Errors are passed through untouched. It runs while polling the stream, so
+    /// it should be cheap and must not block
+    pub fn map_event<F>(mut self, map_event: F) -> Self
+    where
+        F: FnMut(Event) -> Event + Send + 'static,
+    {
+        self.map_event = Some(Box::new(map_event));
+        self
+    }
+
+    /// Only yield the [`Event::Message`]s whose `event` field is one of `types`, dropping the
+    /// others. Other events and errors are passed through. This replaces any filter set by
+    /// [`EventSource::exclude_events`]
+    pub fn only_events(mut self, types: impl Into<HashSet<String>>) -> Self {
+        self.event_filter = Some(EventFilter::Only(types.into()));
+        self
+    }
+
+    /// Drop the [`Event::Message`]s whose `event` field is one of `types`. Other events and
+    /// errors are passed through. This replaces any filter set by [`EventSource::only_events`]
+    pub fn exclude_events(mut self, types: impl Into<HashSet<String>>) -> Self {
+        self.event_filter = Some(EventFilter::Exclude(types.into()));
+        self
+    }
+
+    /// Yield only the [`MessageEvent`]s, skipping other events. Errors are passed through unchanged
+    pub fn messages(self) -> crate::adapters::Messages<R> {
+        crate::adapters::Messages::new(self)
+    }
+
+    /// Like [`EventSource::messages`], but yield the messages as [`SimpleEvent`](crate::SimpleEvent)s
+    pub fn messages_simple(self) -> crate::adapters::SimpleMessages<R> {
+        crate::adapters::SimpleMessages::new(self)
+    }
+
+    /// Combine consecutive messages into one until the data of a message matches `predicate`,
+    /// e.g. to reassemble a response a server sends in chunks ending with a marker
+    pub fn coalesce_until<F>(self, predicate: F) -> crate::adapters::CoalesceUntil<F, R>
+    where
+        F: FnMut(&str) -> bool,
+    {
+        crate::adapters::CoalesceUntil::new(self, predicate)
+    }
+
+    /// Collect the events into batches of up to `max` events, yielding a batch when it is full or
+    /// `max_delay` after its first event, e.g. to process events in bulk with fewer wakeups
+    pub fn batched(self, max: usize, max_delay: Duration) -> crate::adapters::Batched<R> {
+        crate::adapters::Batched::new(self, max, max_delay)
+    }
+
+    /// Deserialize the data of each message as JSON, skipping other events. Requires the `json`
+    /// feature
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::de::DeserializeOwned>(self) -> crate::adapters::Json<T, R> {
+        crate::adapters::Json::new(self)
+    }
+
+    /// Get the connection and event counters of this EventSource
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub(crate) fn clock(&self) -> &ClockHandle {
+        &self.clock
+    }
+
+    /// Get why the EventSource closed, or `None` if it is not closed
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+
     /// Get the current ready state
     pub fn ready_state(&self) -> ReadyState {
         if self.is_closed {
             ReadyState::Closed
-        } else if self.delay.is_some() || self.next_response.is_some() {
-            ReadyState::Connecting
-        } else {
+        } else if self.cur_stream.is_some() {
             ReadyState::Open
+        } else {
+            ReadyState::Connecting
         }
     }
-}
 
-fn check_response(response: Response) -> Result<Response, Error> {
-    match response.status() {
-        StatusCode::OK => {}
-        status => {
-            return Err(Error::InvalidStatusCode(status, response));
+    /// Get a receiver that is notified whenever the [`ReadyState`] changes. Requires the `watch`
+    /// feature
+    #[cfg(feature = "watch")]
+    pub fn ready_state_changes(&self) -> tokio::sync::watch::Receiver<ReadyState> {
+        self.ready_state_tx.subscribe()
+    }
+
+    /// Wait for the next item of the stream, like `StreamExt::next` but without importing it.
+    /// Returns `None` once the EventSource is closed and its [`Event::Closed`] was yielded
+    pub async fn next_event(&mut self) -> Option<Result<Event, Error>>
+    where
+        R: Unpin,
+    {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Collect the items that are ready without waiting, e.g. events already received by the
+    /// current connection, by polling the stream until it is pending or ends. Meant for tests
+    pub fn try_drain(&mut self) -> Vec<Result<Event, Error>>
+    where
+        R: Unpin,
+    {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut items = Vec::new();
+        while let Poll::Ready(Some(item)) = Pin::new(&mut *self).poll_next(&mut cx) {
+            items.push(item);
         }
+        items
     }
-    let content_type =
-        if let Some(content_type) = response.headers().get(&reqwest::header::CONTENT_TYPE) {
-            content_type
-        } else {
-            return Err(Error::InvalidContentType(
-                HeaderValue::from_static(""),
-                response,
-            ));
-        };
-    if content_type
-        .to_str()
-        .map_err(|_| ())
-        .and_then(|s| s.parse::<mime::Mime>().map_err(|_| ()))
-        .map(|mime_type| {
-            matches!(
-                (mime_type.type_(), mime_type.subtype()),
-                (mime::TEXT, mime::EVENT_STREAM)
+
+    #[cfg(feature = "watch")]
+    fn notify_ready_state(&self) {
+        let ready_state = self.ready_state();
+        self.ready_state_tx.send_if_modified(|state| {
+            let modified = *state != ready_state;
+            *state = ready_state;
+            modified
+        });
+    }
+}
+
+impl<R: RetryPolicy> fmt::Debug for EventSource<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventSource")
+            .field("ready_state", &self.ready_state())
+            .field("builder", &self.builder)
+            .field(
+                "next_response",
+                &self.next_response.as_ref().map(|_| "<response future>"),
             )
-        })
-        .unwrap_or(false)
-    {
-        Ok(response)
+            .field(
+                "cur_stream",
+                &self.cur_stream.as_ref().map(|_| "<event stream>"),
+            )
+            .field("delay", &self.delay.as_ref().map(|_| "<delay>"))
+            .field("is_closed", &self.is_closed)
+            .field("retry_policy", &"<retry policy>")
+            .field("last_event_id", &self.last_event_id)
+            .field("last_retry", &self.last_retry)
+            .finish_non_exhaustive()
+    }
+}
+
+fn prepare_builder(
+    builder: RequestBuilder,
+    accept: Option<&HeaderValue>,
+) -> Result<RequestBuilder, CannotCloneRequestError> {
+    let (client, request) = builder.build_split();
+    let mut request = request.map_err(|_| CannotCloneRequestError::invalid_request())?;
+    // only add headers valid for any HTTP version, e.g. no `Connection: keep-alive` which HTTP/2
+    // forbids
+    if let Some(accept) = accept {
+        if !request.headers().contains_key(reqwest::header::ACCEPT) {
+            request
+                .headers_mut()
+                .insert(reqwest::header::ACCEPT, accept.clone());
+        }
+    }
+    let builder = RequestBuilder::from_parts(client, request);
+    if builder.try_clone().is_none() {
+        return Err(CannotCloneRequestError::streaming_body());
+    }
+    Ok(builder)
+}
+
+fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+    const RAW: RawWaker = RawWaker::new(core::ptr::null(), &VTABLE);
+    // SAFETY: the vtable functions do nothing and never use the data pointer
+    unsafe { Waker::from_raw(RAW) }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn response_version(response: &Response) -> Option<Version> {
+    Some(response.version())
+}
+#[cfg(target_arch = "wasm32")]
+fn response_version(_response: &Response) -> Option<Version> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn response_remote_addr(response: &Response) -> Option<SocketAddr> {
+    response.remote_addr()
+}
+#[cfg(target_arch = "wasm32")]
+fn response_remote_addr(_response: &Response) -> Option<SocketAddr> {
+    None
+}
+
+/// Parse a `Content-Type`, ignoring the case of the type and subtype. When it is malformed, e.g.
+/// padded with whitespace or with a parameter missing its value, only its essence is parsed
+fn parse_content_type(content_type: &str) -> Option<mime::Mime> {
+    content_type.parse().ok().or_else(|| {
+        let essence = content_type.split(';').next()?.trim();
+        essence.parse().ok()
+    })
+}
+
+/// Get the `Content-Encoding` of a response that is still compressed. reqwest removes the header
+/// when it decompresses the body, so one that is left means the matching feature is not enabled
+#[cfg(not(target_arch = "wasm32"))]
+fn undecoded_content_encoding(response: &Response) -> Option<HeaderValue> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .filter(|encoding| !encoding.as_bytes().eq_ignore_ascii_case(b"identity"))
+        .cloned()
+}
+
+/// The browser always decompresses the body, but keeps the header
+#[cfg(target_arch = "wasm32")]
+fn undecoded_content_encoding(_response: &Response) -> Option<HeaderValue> {
+    None
+}
+
+#[allow(clippy::result_large_err)]
+fn check_response(
+    response: Response,
+    action: StatusAction,
+    accept_content_type: &AcceptContentType,
+) -> Result<Response, Error> {
+    if action != StatusAction::Accept {
+        return Err(Error::InvalidStatusCode(response.status(), response));
+    }
+    let content_type = response.headers().get(&reqwest::header::CONTENT_TYPE);
+    let mime_type = content_type
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(parse_content_type);
+    if accept_content_type(mime_type.as_ref()) {
+        match undecoded_content_encoding(&response) {
+            Some(encoding) => Err(Error::UnsupportedContentEncoding(encoding, response)),
+            None => Ok(response),
+        }
+    } else if let Some(content_type) = content_type.cloned() {
+        Err(Error::InvalidContentType(content_type, response))
     } else {
-        Err(Error::InvalidContentType(content_type.clone(), response))
+        Err(Error::MissingContentType(response))
     }
 }
 
-impl<'a> EventSourceProjection<'a> {
+impl<'a, R: RetryPolicy> EventSourceProjection<'a, R> {
+    fn reset_idle_delay(&mut self) {
+        let idle_delay = self.idle_timeout.map(|timeout| self.clock.delay(timeout));
+        self.idle_delay.set(idle_delay);
+    }
+
+    fn reset_heartbeat_delay(&mut self) {
+        let heartbeat_delay = self
+            .heartbeat_timeout
+            .map(|timeout| self.clock.delay(timeout));
+        self.heartbeat_delay.set(heartbeat_delay);
+    }
+
+    fn close(&mut self, reason: CloseReason) {
+        *self.is_closed = true;
+        self.close_reason.get_or_insert(reason);
+    }
+
+    fn finish(&mut self, reason: CloseReason) {
+        self.clear_fetch();
+        self.close(reason);
+        *self.closed_emitted = true;
+    }
+
     fn clear_fetch(&mut self) {
+        self.next_headers.take();
         self.next_response.take();
+        self.connect_delay.take();
         self.cur_stream.take();
+        self.idle_delay.take();
+        self.heartbeat_delay.take();
+        self.stats.connected_at.take();
     }
 
-    fn retry_fetch(&mut self) -> Result<(), Error> {
+    #[allow(clippy::result_large_err)]
+    fn fetch(&mut self, headers: Option<HeaderMap>) -> Result<(), Error> {
         self.cur_stream.take();
-        let req = self.builder.try_clone().unwrap().header(
-            HeaderName::from_static("last-event-id"),
-            HeaderValue::from_str(self.last_event_id)
-                .map_err(|_| Error::InvalidLastEventId(self.last_event_id.clone()))?,
+        let mut req = self.builder.try_clone().unwrap();
+        if let Some(body_factory) = self.body_factory {
+            req = req.body(body_factory().map_err(Error::BodyFactory)?);
+        }
+        if let Some(headers) = headers {
+            req = req.headers(headers);
+        }
+        if !self.last_event_id.is_empty() {
+            // `RequestBuilder::headers` replaces existing values rather than appending, so a
+            // `Last-Event-ID` set on the original builder is overwritten
+            let mut headers = HeaderMap::with_capacity(1);
+            let last_event_id = match self.last_event_id_bytes {
+                Some(id) => id.clone(),
+                None => HeaderValue::from_str(self.last_event_id)
+                    .map_err(|_| Error::InvalidLastEventId(self.last_event_id.clone()))?,
+            };
+            headers.insert(HeaderName::from_static("last-event-id"), last_event_id);
+            req = req.headers(headers);
+        }
+        if let Some(before_request) = self.before_request.as_mut() {
+            req = before_request(req);
+        }
+        debug!(
+            retry_num = self.last_retry.map(|retry| retry.0),
+            "connecting"
         );
         let res_future = Box::pin(req.send());
         self.next_response.replace(res_future);
+        *self.fetched_at = Some(self.clock.now());
+        let retry_num = self.last_retry.map(|retry| retry.0).unwrap_or(0);
+        self.connect_delay.set(
+            self.connect_timeout
+                .as_ref()
+                .map(|connect_timeout| self.clock.delay(connect_timeout(retry_num))),
+        );
         Ok(())
     }
 
-    fn handle_response(&mut self, res: Response) {
-        self.last_retry.take();
-        let mut stream = res.bytes_stream().eventsource();
+    fn handle_response(&mut self, res: Response) -> OpenEvent {
+        debug!(status = %res.status(), "connection opened");
+        let open = OpenEvent {
+            status: res.status(),
+            headers: res.headers().clone(),
+            version: response_version(&res),
+            remote_addr: response_remote_addr(&res),
+            reconnect: self.stats.connections > 0,
+            retry_num: self.last_retry.map(|retry| retry.0).unwrap_or(0),
+            connect_duration: self
+                .fetched_at
+                .take()
+                .map(|fetched_at| self.clock.now().saturating_duration_since(fetched_at))
+                .unwrap_or_default(),
+        };
+        telemetry::connect(open.connect_duration);
+        if let Some(on_response) = self.on_response.as_mut() {
+            on_response(&res);
+        }
+        if *self.follow_redirect_target {
+            self.retarget(res.url());
+        }
+        self.retry_policy.on_success(*self.last_retry);
+        self.stats.connections += 1;
+        self.stats.connected_at = Some(self.clock.now());
+        if self.reset_backoff_after.is_some() {
+            // only reset the backoff once the connection proved stable, see `handle_event`
+            *self.opened_at = Some(self.clock.now());
+        } else {
+            self.last_retry.take();
+        }
+        let mut stream = EventStream::new(Box::pin(res.bytes_stream()) as ByteStream);
         stream.set_last_event_id(self.last_event_id.clone());
-        self.cur_stream.replace(Box::pin(stream));
+        stream.set_max_event_size(*self.max_event_size);
+        stream.set_max_field_size(*self.max_field_size);
+        stream.set_lenient(*self.lenient_parsing);
+        stream.set_report_invalid_retry(*self.report_invalid_retry);
+        stream.set_report_partial_events(*self.report_partial_events);
+        self.cur_stream.replace(stream);
+        self.reset_idle_delay();
+        self.reset_heartbeat_delay();
+        open
+    }
+
+    /// Send the next requests to `url` if the last one was redirected there
+    fn retarget(&mut self, url: &Url) {
+        let (client, request) = self.builder.try_clone().unwrap().build_split();
+        if let Ok(mut request) = request {
+            if request.url() != url {
+                debug!(%url, "following redirect target");
+                *request.url_mut() = url.clone();
+                *self.builder = RequestBuilder::from_parts(client, request);
+            }
+        }
     }
 
     fn handle_event(&mut self, event: &MessageEvent) {
-        *self.last_event_id = event.id.clone();
-        if let Some(duration) = event.retry {
-            self.retry_policy.set_reconnection_time(duration)
+        if let (Some(window), Some(opened_at)) = (*self.reset_backoff_after, *self.opened_at) {
+            if self.clock.now().saturating_duration_since(opened_at) >= window {
+                self.last_retry.take();
+                self.opened_at.take();
+            }
+        }
+        self.stats.events += 1;
+        telemetry::event();
+        trace!(id = %event.id, event = %event.event, "received message");
+        self.reset_idle_delay();
+        if event.id != *self.last_event_id {
+            *self.last_event_id = event.id.clone();
+            self.last_event_id_bytes.take();
+        }
+    }
+
+    /// Handle `error` cutting off the current connection, first yielding the data of an incomplete
+    /// event if partial events are reported
+    fn cut_off(&mut self, error: Error) -> Poll<Option<Result<Event, Error>>> {
+        let partial = self
+            .cur_stream
+            .as_mut()
+            .as_pin_mut()
+            .and_then(|stream| stream.get_mut().take_partial_event());
+        self.handle_error(&error);
+        match partial {
+            Some(partial) => {
+                *self.pending_error = Some(error);
+                Poll::Ready(Some(Err(partial)))
+            }
+            None => Poll::Ready(Some(Err(error))),
         }
     }
 
     fn handle_error(&mut self, error: &Error) {
         self.clear_fetch();
+        if *self.draining {
+            self.close(CloseReason::UserClosed);
+            return;
+        }
         if let Some(retry_delay) = self.retry_policy.retry(error, *self.last_retry) {
-            let retry_num = self.last_retry.map(|retry| retry.0).unwrap_or(1);
+            // never reconnect sooner than the server asked for
+            let retry_delay = error
+                .retry_after()
+                .map_or(retry_delay, |retry_after| retry_after.max(retry_delay));
+            let retry_num = self.last_retry.map(|retry| retry.0 + 1).unwrap_or(1);
+            debug!(%error, retry_num, ?retry_delay, "retrying");
             *self.last_retry = Some((retry_num, retry_delay));
-            self.delay.replace(Delay::new(retry_delay));
+            self.delay.replace(self.clock.delay(retry_delay));
+            // a delay too large for an `Instant` never fires in practice
+            *self.reconnect_at = self.clock.now().checked_add(retry_delay);
         } else {
-            *self.is_closed = true;
+            debug!(%error, "not retrying");
+            self.close(if error.is_retryable() {
+                CloseReason::RetriesExhausted
+            } else {
+                CloseReason::Fatal
+            });
         }
     }
 }
 
-/// Events created by the [`EventSource`]
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum Event {
-    /// The event fired when the connection is opened
-    Open,
-    /// The event fired when a [`MessageEvent`] is received
-    Message(MessageEvent),
+/// Counters describing the lifetime of an [`EventSource`], see [`EventSource::stats`]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// The number of connections that were opened
+    pub connections: u64,
+    /// The number of messages received, including ones dropped by an event filter
+    pub events: u64,
+    /// The number of messages dropped by an event filter, see [`EventSource::only_events`] and
+    /// [`EventSource::exclude_events`]
+    pub filtered: u64,
+    /// The number of comments received, including ones that were not yielded
+    pub comments: u64,
+    /// The number of reconnect attempts, either by the retry policy or [`EventSource::reconnect`]
+    pub reconnects: u64,
+    /// When the current connection was opened, if connected. This is a `web_time::Instant` on
+    /// wasm
+    pub connected_at: Option<Instant>,
 }
 
-impl From<MessageEvent> for Event {
-    fn from(event: MessageEvent) -> Self {
-        Event::Message(event)
+impl<R: RetryPolicy> Stream for EventSource<R> {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut this = self.as_mut().project();
+        let poll = match (this.poll_event(cx), this.map_event.as_mut()) {
+            (Poll::Ready(Some(Ok(event))), Some(map_event)) => {
+                Poll::Ready(Some(Ok(map_event(event))))
+            }
+            (poll, _) => poll,
+        };
+        if let Poll::Ready(Some(Err(err))) = &poll {
+            telemetry::error(err);
+            if let Some(on_error) = this.on_error.as_mut() {
+                on_error(err);
+            }
+        }
+        #[cfg(feature = "watch")]
+        self.notify_ready_state();
+        poll
     }
 }
 
-impl Stream for EventSource {
-    type Item = Result<Event, Error>;
+impl<'a, R: RetryPolicy> EventSourceProjection<'a, R> {
+    fn poll_event(&mut self, cx: &mut Context) -> Poll<Option<Result<Event, Error>>> {
+        if let Some(err) = self.pending_error.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let mut this = self.project();
+        if *self.is_closed {
+            if *self.closed_emitted {
+                return Poll::Ready(None);
+            }
+            *self.closed_emitted = true;
+            debug!("closed");
+            return Poll::Ready(Some(Ok(Event::Closed)));
+        }
 
-        if *this.is_closed {
-            return Poll::Ready(None);
+        if let Some(lifetime_delay) = self.lifetime_delay.as_mut().as_pin_mut() {
+            if lifetime_delay.poll(cx).is_ready() {
+                self.lifetime_delay.set(None);
+                self.delay.set(None);
+                self.finish(CloseReason::Expired);
+                debug!("lifetime elapsed, closed");
+                return Poll::Ready(Some(Ok(Event::Closed)));
+            }
+        }
+
+        if *self.paused {
+            *self.resume_waker = Some(cx.waker().clone());
+            return Poll::Pending;
         }
 
-        if let Some(delay) = this.delay.as_mut().as_pin_mut() {
+        if let Some(delay) = self.delay.as_mut().as_pin_mut() {
             match delay.poll(cx) {
                 Poll::Ready(_) => {
-                    this.delay.take();
-                    if let Err(err) = this.retry_fetch() {
-                        *this.is_closed = true;
-                        return Poll::Ready(Some(Err(err)));
+                    self.delay.take();
+                    self.stats.reconnects += 1;
+                    telemetry::reconnect();
+                    if let (Some(on_reconnect), Some((retry_num, retry_delay))) =
+                        (self.on_reconnect.as_mut(), *self.last_retry)
+                    {
+                        on_reconnect(retry_num, retry_delay);
                     }
                 }
                 Poll::Pending => return Poll::Pending,
             }
         }
 
-        if let Some(response_future) = this.next_response.as_mut().as_pin_mut() {
+        if self.next_response.is_none() && self.cur_stream.is_none() {
+            let headers = match self.header_provider.as_ref() {
+                Some(header_provider) => {
+                    if self.next_headers.is_none() {
+                        self.next_headers.set(Some(header_provider()));
+                    }
+                    let headers_future = self.next_headers.as_mut().as_pin_mut().unwrap();
+                    let headers = ready!(headers_future.poll(cx));
+                    self.next_headers.set(None);
+                    Some(headers)
+                }
+                None => None,
+            };
+            if let Err(err) = self.fetch(headers) {
+                match err {
+                    // the factory may succeed on the next attempt
+                    Error::BodyFactory(_) => self.handle_error(&err),
+                    _ => self.close(CloseReason::Fatal),
+                }
+                return Poll::Ready(Some(Err(err)));
+            }
+        }
+
+        if let Some(response_future) = self.next_response.as_mut().as_pin_mut() {
             match response_future.poll(cx) {
                 Poll::Ready(Ok(res)) => {
-                    this.clear_fetch();
-                    match check_response(res) {
+                    self.clear_fetch();
+                    let action = (self.accept_status)(res.status());
+                    if action == StatusAction::Close {
+                        self.finish(CloseReason::StreamFinished);
+                        debug!(status = %res.status(), "closed by response status");
+                        return Poll::Ready(Some(Ok(Event::Closed)));
+                    }
+                    match check_response(res, action, self.accept_content_type) {
                         Ok(res) => {
-                            this.handle_response(res);
-                            return Poll::Ready(Some(Ok(Event::Open)));
+                            let open = self.handle_response(res);
+                            if *self.emit_open {
+                                return Poll::Ready(Some(Ok(Event::Open(open))));
+                            }
+                        }
+                        Err(err @ Error::InvalidStatusCode(..)) => {
+                            self.handle_error(&err);
+                            return Poll::Ready(Some(Err(err)));
                         }
                         Err(err) => {
-                            *this.is_closed = true;
+                            self.close(CloseReason::Fatal);
                             return Poll::Ready(Some(Err(err)));
                         }
                     }
                 }
                 Poll::Ready(Err(err)) => {
                     let err = Error::Transport(err);
-                    this.handle_error(&err);
+                    self.handle_error(&err);
                     return Poll::Ready(Some(Err(err)));
                 }
                 Poll::Pending => {
+                    if let Some(connect_delay) = self.connect_delay.as_mut().as_pin_mut() {
+                        if connect_delay.poll(cx).is_ready() {
+                            let err = Error::ConnectTimeout;
+                            self.handle_error(&err);
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    }
                     return Poll::Pending;
                 }
             }
         }
 
-        match this
-            .cur_stream
-            .as_mut()
-            .as_pin_mut()
-            .unwrap()
-            .as_mut()
-            .poll_next(cx)
-        {
-            Poll::Ready(Some(Err(err))) => {
-                let err = err.into();
-                this.handle_error(&err);
-                Poll::Ready(Some(Err(err)))
-            }
-            Poll::Ready(Some(Ok(event))) => {
-                this.handle_event(&event);
-                Poll::Ready(Some(Ok(event.into())))
+        loop {
+            let stream = self.cur_stream.as_mut().as_pin_mut().unwrap().get_mut();
+            let item = Pin::new(&mut *stream).poll_next(cx);
+            // a `retry` field applies even to events without data, e.g. a final event sent right
+            // before the server ends the stream to ask for a later reconnect
+            if let Some(duration) = stream.take_reconnection_time() {
+                self.retry_policy.set_reconnection_time(duration);
             }
-            Poll::Ready(None) => {
-                let err = Error::StreamEnded;
-                this.handle_error(&err);
-                Poll::Ready(Some(Err(err)))
+            match item {
+                Poll::Ready(Some(Err(
+                    err @ (Error::InvalidRetryField(_) | Error::PartialEvent(_)),
+                ))) => {
+                    // diagnostics, which do not end the connection by themselves
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    self.handle_error(&err);
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Ready(Some(Ok(Event::Message(event)))) => {
+                    // filtered events still carry the last event id and reconnection time
+                    self.handle_event(&event);
+                    match self.event_filter {
+                        Some(filter) if !filter.matches(&event) => self.stats.filtered += 1,
+                        _ => return Poll::Ready(Some(Ok(event.into()))),
+                    }
+                }
+                Poll::Ready(Some(Ok(comment))) => {
+                    // comments are commonly sent as keep-alives, so they show the connection is
+                    // not idle even when they are not yielded
+                    self.reset_idle_delay();
+                    self.reset_heartbeat_delay();
+                    self.stats.comments += 1;
+                    if *self.emit_comments {
+                        return Poll::Ready(Some(Ok(comment)));
+                    }
+                }
+                Poll::Ready(None) if *self.draining => {
+                    self.finish(CloseReason::UserClosed);
+                    debug!("stream ended, closed");
+                    return Poll::Ready(Some(Ok(Event::Closed)));
+                }
+                Poll::Ready(None) if !*self.treat_stream_end_as_error => {
+                    self.finish(CloseReason::StreamFinished);
+                    debug!("stream ended, closed");
+                    return Poll::Ready(Some(Ok(Event::Closed)));
+                }
+                Poll::Ready(None) => {
+                    let err = Error::StreamEnded;
+                    self.handle_error(&err);
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Pending => {
+                    if let Some(idle_delay) = self.idle_delay.as_mut().as_pin_mut() {
+                        if idle_delay.poll(cx).is_ready() {
+                            return self.cut_off(Error::IdleTimeout);
+                        }
+                    }
+                    if let Some(heartbeat_delay) = self.heartbeat_delay.as_mut().as_pin_mut() {
+                        if heartbeat_delay.poll(cx).is_ready() {
+                            return self.cut_off(Error::HeartbeatTimeout);
+                        }
+                    }
+                    return Poll::Pending;
+                }
             }
-            Poll::Pending => Poll::Pending,
         }
     }
 }
+
+// `&mut EventSource` implementing `Stream` relies on this
+const _: fn() = || {
+    fn assert_unpin<T: Unpin>() {}
+    assert_unpin::<EventSource>();
+};
+
+impl<R: RetryPolicy> FusedStream for EventSource<R> {
+    fn is_terminated(&self) -> bool {
+        self.is_closed && self.closed_emitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockClock, Reply, Server};
+    use futures::StreamExt;
+
+    fn response(headers: &[(&'static str, &'static str)]) -> Response {
+        let mut builder = http::Response::builder().status(StatusCode::OK);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body("").unwrap().into()
+    }
+
+    fn accepts(content_type: &str) -> bool {
+        default_accept_content_type(parse_content_type(content_type).as_ref())
+    }
+
+    #[test]
+    fn content_type_case_insensitive() {
+        assert!(accepts("text/event-stream"));
+        assert!(accepts("Text/Event-Stream"));
+        assert!(accepts("TEXT/EVENT-STREAM"));
+    }
+
+    #[test]
+    fn content_type_with_parameters() {
+        assert!(accepts("text/event-stream; charset=UTF-8"));
+        assert!(accepts("text/event-stream;charset=utf-8"));
+    }
+
+    #[test]
+    fn content_type_malformed() {
+        assert!(accepts("  text/event-stream  "));
+        assert!(accepts("text/event-stream; charset"));
+        assert!(accepts("text/event-stream; charset="));
+        assert!(!accepts("text/plain"));
+        assert!(!accepts("event-stream"));
+        assert!(!accepts(""));
+    }
+
+    #[test]
+    fn content_type_missing() {
+        assert!(!default_accept_content_type(None));
+        let result = check_response(
+            response(&[]),
+            StatusAction::Accept,
+            &default_accept_content_type,
+        );
+        assert!(matches!(result, Err(Error::MissingContentType(_))));
+        let result = check_response(
+            response(&[("content-type", "text/plain")]),
+            StatusAction::Accept,
+            &default_accept_content_type,
+        );
+        assert!(matches!(result, Err(Error::InvalidContentType(..))));
+    }
+
+    #[test]
+    fn content_encoding_identity() {
+        let response = response(&[
+            ("content-type", "text/event-stream"),
+            ("content-encoding", "identity"),
+        ]);
+        assert_eq!(undecoded_content_encoding(&response), None);
+        let result = check_response(response, StatusAction::Accept, &default_accept_content_type);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn content_encoding_undecoded() {
+        let response = response(&[
+            ("content-type", "text/event-stream"),
+            ("content-encoding", "gzip"),
+        ]);
+        assert_eq!(
+            undecoded_content_encoding(&response),
+            Some(HeaderValue::from_static("gzip"))
+        );
+        let result = check_response(response, StatusAction::Accept, &default_accept_content_type);
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedContentEncoding(encoding, _)) if encoding == "gzip"
+        ));
+    }
+
+    #[test]
+    fn content_encoding_decoded() {
+        // reqwest removes the header once it decompressed the body
+        let response = response(&[("content-type", "text/event-stream")]);
+        assert_eq!(undecoded_content_encoding(&response), None);
+        let result = check_response(response, StatusAction::Accept, &default_accept_content_type);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn content_encoding_of_rejected_response() {
+        // the content type is checked first, as a compressed error page is not an event stream
+        let response = response(&[("content-type", "text/html"), ("content-encoding", "gzip")]);
+        let result = check_response(response, StatusAction::Accept, &default_accept_content_type);
+        assert!(matches!(result, Err(Error::InvalidContentType(..))));
+    }
+
+    #[tokio::test]
+    async fn partial_event_before_idle_timeout() {
+        let clock = MockClock::new();
+        let server = Server::new(|_| Reply::events("data: partial\n:ping\n").hold());
+        let mut source = EventSource::builder(Client::new().get(server.url()))
+            .idle_timeout(Duration::from_secs(1))
+            .report_partial_events(true)
+            .emit_comments(true)
+            .emit_open(false)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        assert_eq!(
+            source.next().await.unwrap().unwrap(),
+            Event::Comment("ping".into())
+        );
+        clock.advance(Duration::from_secs(1));
+        assert!(matches!(
+            source.next().await,
+            Some(Err(Error::PartialEvent(data))) if data == "partial"
+        ));
+        assert!(matches!(source.next().await, Some(Err(Error::IdleTimeout))));
+        assert!(source.time_until_reconnect().is_some());
+    }
+
+    #[test]
+    fn set_last_event_id_bytes() {
+        let mut source = EventSource::get("http://localhost/").unwrap();
+        source.set_last_event_id_bytes(b"\xFF1").unwrap();
+        assert_eq!(source.last_event_id_bytes(), b"\xFF1");
+        assert_eq!(source.last_event_id(), "\u{FFFD}1");
+        assert!(source.set_last_event_id_bytes(b"1\n2").is_err());
+        assert_eq!(source.last_event_id_bytes(), b"\xFF1");
+    }
+
+    #[tokio::test]
+    async fn close_graceful_drains_the_connection() {
+        let server = Server::new(|_| Reply::events("data: a\n\ndata: b\n\n"));
+        let mut source = EventSource::get(server.url()).unwrap();
+        assert!(matches!(source.next().await, Some(Ok(Event::Open(_)))));
+        source.close_graceful();
+        assert_eq!(source.ready_state(), ReadyState::Open);
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("a"));
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("b"));
+        // the stream ending closes the EventSource instead of reconnecting
+        assert_eq!(source.next().await.unwrap().unwrap(), Event::Closed);
+        assert_eq!(source.close_reason(), Some(CloseReason::UserClosed));
+        assert!(source.next().await.is_none());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn close_graceful_while_connecting() {
+        let server = Server::new(|_| Reply::events("data: a\n\n"));
+        let mut source = EventSource::get(server.url()).unwrap();
+        source.close_graceful();
+        assert_eq!(source.next().await.unwrap().unwrap(), Event::Closed);
+        assert!(source.next().await.is_none());
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let server = Server::new(|_| Reply::events("data: a\n\n").hold());
+        let mut source = EventSource::get(server.url()).unwrap();
+        assert!(matches!(source.next().await, Some(Ok(Event::Open(_)))));
+        source.pause();
+        assert!(source.is_paused());
+        // a paused EventSource does not read the connection, so the event waits for the resume
+        assert!(source.try_drain().is_empty());
+        source.resume();
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn close_while_paused() {
+        let server = Server::new(|_| Reply::events("data: a\n\n").hold());
+        let mut source = EventSource::get(server.url()).unwrap();
+        assert!(matches!(source.next().await, Some(Ok(Event::Open(_)))));
+        source.pause();
+        source.close();
+        assert_eq!(source.next().await.unwrap().unwrap(), Event::Closed);
+        assert!(source.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn reconnect_resumes_from_last_event_id() {
+        let server = Server::new(|n| match n {
+            0 => Reply::events("id: 1\ndata: a\n\n").hold(),
+            _ => Reply::events("data: b\n\n").hold(),
+        });
+        let mut source = EventSource::get(server.url()).unwrap();
+        assert!(matches!(source.next().await, Some(Ok(Event::Open(_)))));
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("a"));
+        source.reconnect();
+        assert_eq!(source.ready_state(), ReadyState::Connecting);
+        match source.next().await {
+            Some(Ok(Event::Open(open))) => assert!(open.reconnect),
+            item => panic!("expected an open event, got {:?}", item),
+        }
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("b"));
+        assert_eq!(source.stats().reconnects, 1);
+        assert!(server.requests()[1].contains("last-event-id: 1\r\n"));
+
+        source.close();
+        source.reconnect();
+        assert_eq!(source.next().await.unwrap().unwrap(), Event::Closed);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn max_lifetime_closes_the_connection() {
+        let clock = MockClock::new();
+        let server = Server::new(|_| Reply::events("data: a\n\n").hold());
+        let mut source = EventSource::builder(Client::new().get(server.url()))
+            .max_lifetime(Duration::from_secs(10))
+            .emit_open(false)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("a"));
+        clock.advance(Duration::from_secs(9));
+        assert!(source.try_drain().is_empty());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(source.next().await.unwrap().unwrap(), Event::Closed);
+        assert_eq!(source.close_reason(), Some(CloseReason::Expired));
+        assert!(source.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn max_lifetime_elapses_while_retrying() {
+        let clock = MockClock::new();
+        let server = Server::new(|_| Reply::status(503));
+        let mut source = EventSource::builder(Client::new().get(server.url()))
+            .max_lifetime(Duration::from_secs(10))
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            source.next().await,
+            Some(Err(Error::InvalidStatusCode(
+                StatusCode::SERVICE_UNAVAILABLE,
+                _
+            )))
+        ));
+        assert!(source.time_until_reconnect().is_some());
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(source.next().await.unwrap().unwrap(), Event::Closed);
+        assert_eq!(source.close_reason(), Some(CloseReason::Expired));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reset_backoff_after_stable_connection() {
+        let clock = MockClock::new();
+        let (gate, rest) = std::sync::mpsc::channel();
+        let rest = std::sync::Mutex::new(Some(rest));
+        let server = Server::new(move |n| match n {
+            0 => Reply::status(503),
+            _ => Reply::events("data: a\n\n")
+                .then(rest.lock().unwrap().take().unwrap(), "data: b\n\n")
+                .hold(),
+        });
+        let mut source = EventSource::builder(Client::new().get(server.url()))
+            .reset_backoff_after(Duration::from_secs(10))
+            .emit_open(false)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        assert!(source.next().await.unwrap().is_err());
+        let (retry_num, delay) = source.last_retry().unwrap();
+        assert_eq!(retry_num, 1);
+        clock.advance(delay);
+        // an event right after the connection opened does not reset the backoff
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("a"));
+        assert_eq!(source.last_retry(), Some((1, delay)));
+        clock.advance(Duration::from_secs(10));
+        gate.send(()).unwrap();
+        assert_eq!(source.next().await.unwrap().unwrap().data(), Some("b"));
+        assert_eq!(source.last_retry(), None);
+    }
+}
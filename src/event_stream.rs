@@ -0,0 +1,710 @@
+//! Parses a stream of bytes into [`MessageEvent`]s and comments. Adapted from [`eventsource_stream`] so the
+//! [`EventSource`] can inspect and limit what is buffered while an event is incomplete.
+
+use crate::error::Error;
+use crate::event::{Event, MessageEvent};
+use crate::parser::{is_bom, is_lf, line, RawEventLine};
+use bytes::Bytes;
+use core::pin::Pin;
+use futures_core::stream::Stream;
+use futures_core::task::{Context, Poll};
+use nom::error::Error as NomError;
+use pin_project_lite::pin_project;
+use reqwest::Error as ReqwestError;
+use std::string::{FromUtf8Error, String};
+use std::time::Duration;
+
+#[cfg(all(doc, feature = "event-source"))]
+use crate::event_source::EventSource;
+
+#[derive(Default, Debug)]
+struct EventBuilder {
+    event: MessageEvent,
+    is_complete: bool,
+    invalid_retry: Option<String>,
+    reconnection_time: Option<Duration>,
+}
+
+impl EventBuilder {
+    /// From the HTML spec
+    ///
+    /// -> If the field name is "event"
+    ///    Set the event type buffer to field value.
+    ///
+    /// -> If the field name is "data"
+    ///    Append the field value to the data buffer, then append a single U+000A LINE FEED (LF)
+    ///    character to the data buffer.
+    ///
+    /// -> If the field name is "id"
+    ///    If the field value does not contain U+0000 NULL, then set the last event ID buffer
+    ///    to the field value. Otherwise, ignore the field.
+    ///
+    /// -> If the field name is "retry"
+    ///    If the field value consists of only ASCII digits, then interpret the field value as
+    ///    an integer in base ten, and set the event stream's reconnection time to that integer.
+    ///    Otherwise, ignore the field.
+    ///
+    /// -> Otherwise
+    ///    The field is ignored.
+    ///
+    /// In lenient mode, field names are matched ignoring surrounding whitespace and case
+    fn add(&mut self, line: RawEventLine, lenient: bool) {
+        match line {
+            RawEventLine::Field(field, val) => {
+                let val = val.unwrap_or("");
+                let normalized;
+                let field = if lenient {
+                    normalized = field.trim().to_ascii_lowercase();
+                    normalized.as_str()
+                } else {
+                    field
+                };
+                match field {
+                    "event" => {
+                        self.event.event = val.to_string();
+                    }
+                    "data" => {
+                        self.event.data.push_str(val);
+                        self.event.data.push('\u{000A}');
+                    }
+                    "id" if !val.contains('\u{0000}') => {
+                        self.event.id = val.to_string();
+                    }
+                    "retry" => match val.parse::<u64>() {
+                        Ok(val) => {
+                            self.event.retry = Some(Duration::from_millis(val));
+                            self.reconnection_time = self.event.retry;
+                        }
+                        Err(_) => self.invalid_retry = Some(val.to_string()),
+                    },
+                    _ => {}
+                }
+            }
+            RawEventLine::Comment(_) => {}
+            RawEventLine::Empty => self.is_complete = true,
+        }
+    }
+
+    /// From the HTML spec
+    ///
+    /// 1. Set the last event ID string of the event source to the value of the last event ID
+    ///    buffer. The buffer does not get reset, so the last event ID string of the event source
+    ///    remains set to this value until the next time it is set by the server.
+    /// 2. If the data buffer is an empty string, set the data buffer and the event type buffer
+    ///    to the empty string and return.
+    /// 3. If the data buffer's last character is a U+000A LINE FEED (LF) character, then remove
+    ///    the last character from the data buffer.
+    /// 4. Let event be the result of creating an event using MessageEvent, in the relevant Realm
+    ///    of the EventSource object.
+    /// 5. Initialize event's type attribute to message, its data attribute to data, its origin
+    ///    attribute to the serialization of the origin of the event stream's final URL (i.e., the
+    ///    URL after redirects), and its lastEventId attribute to the last event ID string of the
+    ///    event source.
+    /// 6. If the event type buffer has a value other than the empty string, change the type of
+    ///    the newly created event to equal the value of the event type buffer.
+    /// 7. Set the data buffer and the event type buffer to the empty string.
+    /// 8. Queue a task which, if the readyState attribute is set to a value other than CLOSED,
+    ///    dispatches the newly created event at the EventSource object.
+    fn dispatch(&mut self) -> Option<MessageEvent> {
+        let builder = core::mem::take(self);
+        let mut event = builder.event;
+        self.event.id = event.id.clone();
+        self.reconnection_time = builder.reconnection_time;
+
+        if event.data.is_empty() {
+            return None;
+        }
+
+        if is_lf(event.data.chars().next_back().unwrap()) {
+            event.data.pop();
+        }
+
+        if event.event.is_empty() {
+            event.event = "message".to_string();
+        }
+
+        Some(event)
+    }
+
+    /// Take the data of an incomplete event, without its trailing LF, if there is any
+    fn take_data(&mut self) -> Option<String> {
+        if self.event.data.is_empty() {
+            return None;
+        }
+        let mut data = core::mem::take(&mut self.event.data);
+        if data.ends_with('\u{000A}') {
+            data.pop();
+        }
+        Some(data)
+    }
+}
+
+pin_project! {
+/// A Stream of [`Event::Message`]s and [`Event::Comment`]s parsed from a stream of bytes, such
+/// as [`Response::bytes_stream`](reqwest::Response::bytes_stream). It is what an [`EventSource`]
+/// reads each connection with, and can be used on its own to manage connections yourself
+#[project = EventStreamProjection]
+pub struct EventStream<S> {
+    #[pin]
+    stream: S,
+    utf8_buffer: Vec<u8>,
+    buffer: String,
+    builder: EventBuilder,
+    is_started: bool,
+    is_terminated: bool,
+    max_event_size: Option<usize>,
+    max_field_size: Option<usize>,
+    lenient: bool,
+    report_invalid_retry: bool,
+    report_partial_events: bool,
+    pending_error: Option<Error>,
+}
+}
+
+impl<S> EventStream<S> {
+    /// Initialize the EventStream with a Stream
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            utf8_buffer: Vec::new(),
+            buffer: String::new(),
+            builder: EventBuilder::default(),
+            is_started: false,
+            is_terminated: false,
+            max_event_size: None,
+            max_field_size: None,
+            lenient: false,
+            report_invalid_retry: false,
+            report_partial_events: false,
+            pending_error: None,
+        }
+    }
+
+    /// Set the last event ID of the stream, which events without an `id` field inherit
+    pub fn set_last_event_id(&mut self, id: impl Into<String>) {
+        self.builder.event.id = id.into();
+    }
+
+    /// Set the maximum number of bytes buffered for a single incomplete event. The stream ends
+    /// after yielding an [`Error::BufferOverflow`] when an event exceeds it
+    pub fn set_max_event_size(&mut self, max_event_size: Option<usize>) {
+        self.max_event_size = max_event_size;
+    }
+
+    /// Set the maximum number of bytes of a single line, i.e. a field name and its value. The
+    /// stream ends after yielding an [`Error::FieldTooLarge`] when a line exceeds it
+    pub fn set_max_field_size(&mut self, max_field_size: Option<usize>) {
+        self.max_field_size = max_field_size;
+    }
+
+    /// Set whether the stream recovers from deviations from the spec instead of yielding errors
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Set whether a `retry` field that is not a valid integer yields an
+    /// [`Error::InvalidRetryField`] instead of being ignored
+    pub fn set_report_invalid_retry(&mut self, report_invalid_retry: bool) {
+        self.report_invalid_retry = report_invalid_retry;
+    }
+
+    /// Set whether the data of an event cut off by an error, other than an
+    /// [`Error::InvalidRetryField`], or the end of the stream is yielded as an
+    /// [`Error::PartialEvent`] before the error or the end
+    pub fn set_report_partial_events(&mut self, report_partial_events: bool) {
+        self.report_partial_events = report_partial_events;
+    }
+
+    /// Take the reconnection time of the last valid `retry` field. As in the spec, it is set by
+    /// the field itself, so it includes `retry` fields of events without data, which are not
+    /// dispatched as messages
+    pub fn take_reconnection_time(&mut self) -> Option<Duration> {
+        self.builder.reconnection_time.take()
+    }
+
+    /// Take the data of an incomplete event as an [`Error::PartialEvent`] if partial events are
+    /// reported, e.g. before dropping the stream after a timeout
+    #[cfg(feature = "event-source")]
+    pub(crate) fn take_partial_event(&mut self) -> Option<Error> {
+        if !self.report_partial_events {
+            return None;
+        }
+        self.builder.take_data().map(Error::PartialEvent)
+    }
+}
+
+/// Append `bytes` to the bytes left over from the last chunk and decode as much as possible,
+/// keeping an incomplete trailing character for the next chunk
+fn decode_utf8(utf8_buffer: &mut Vec<u8>, bytes: &[u8]) -> Result<String, FromUtf8Error> {
+    utf8_buffer.extend_from_slice(bytes);
+    match String::from_utf8(core::mem::take(utf8_buffer)) {
+        Ok(string) => Ok(string),
+        Err(err) if err.utf8_error().error_len().is_some() => Err(err),
+        Err(err) => {
+            let valid_size = err.utf8_error().valid_up_to();
+            let mut bytes = err.into_bytes();
+            *utf8_buffer = bytes.split_off(valid_size);
+            // SAFETY: the bytes were checked to be valid UTF8 up to `valid_size`
+            Ok(unsafe { String::from_utf8_unchecked(bytes) })
+        }
+    }
+}
+
+/// Like [`decode_utf8`], but replace invalid sequences with U+FFFD REPLACEMENT CHARACTER
+fn decode_utf8_lossy(utf8_buffer: &mut Vec<u8>, bytes: &[u8]) -> String {
+    utf8_buffer.extend_from_slice(bytes);
+    let bytes = core::mem::take(utf8_buffer);
+    let mut string = String::with_capacity(bytes.len());
+    let mut rest = bytes.as_slice();
+    loop {
+        match core::str::from_utf8(rest) {
+            Ok(valid) => {
+                string.push_str(valid);
+                return string;
+            }
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                // SAFETY: the bytes were checked to be valid UTF8 up to `valid_up_to`
+                string.push_str(unsafe { core::str::from_utf8_unchecked(valid) });
+                match err.error_len() {
+                    Some(len) => {
+                        string.push(char::REPLACEMENT_CHARACTER);
+                        rest = &invalid[len..];
+                    }
+                    None => {
+                        *utf8_buffer = invalid.to_vec();
+                        return string;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Put an [`Error::PartialEvent`] with the data of an incomplete event in front of `error` when
+/// `enabled`, keeping `error` for the next poll
+fn with_partial_event(
+    builder: &mut EventBuilder,
+    enabled: bool,
+    pending_error: &mut Option<Error>,
+    error: Option<Error>,
+) -> Option<Error> {
+    if !enabled {
+        return error;
+    }
+    match builder.take_data() {
+        Some(data) => {
+            *pending_error = error;
+            Some(Error::PartialEvent(data))
+        }
+        None => error,
+    }
+}
+
+/// The size of a line without its line ending
+fn field_size(line: &str) -> usize {
+    line.trim_end_matches(['\r', '\n']).len()
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_event(
+    buffer: &mut String,
+    builder: &mut EventBuilder,
+    max_field_size: Option<usize>,
+    lenient: bool,
+    report_invalid_retry: bool,
+) -> Result<Option<Event>, Error> {
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+    loop {
+        match line(buffer.as_ref()) {
+            Ok((rem, next_line)) => {
+                let consumed = buffer.len() - rem.len();
+                if let Some(max_field_size) = max_field_size {
+                    if field_size(&buffer[..consumed]) > max_field_size {
+                        return Err(Error::FieldTooLarge);
+                    }
+                }
+                let comment = match next_line {
+                    RawEventLine::Comment(comment) => {
+                        Some(comment.strip_prefix(' ').unwrap_or(comment).to_string())
+                    }
+                    next_line => {
+                        builder.add(next_line, lenient);
+                        None
+                    }
+                };
+                let rem = buffer.split_off(consumed);
+                *buffer = rem;
+                if let Some(value) = builder.invalid_retry.take() {
+                    if report_invalid_retry {
+                        return Err(Error::InvalidRetryField(value));
+                    }
+                }
+                if let Some(comment) = comment {
+                    return Ok(Some(Event::Comment(comment)));
+                }
+                if builder.is_complete {
+                    if let Some(event) = builder.dispatch() {
+                        return Ok(Some(Event::Message(event)));
+                    }
+                }
+            }
+            Err(nom::Err::Incomplete(_)) => return Ok(None),
+            Err(_) if lenient => {
+                // skip the offending line
+                match buffer.find(['\r', '\n']) {
+                    Some(end) => drop(buffer.drain(..=end)),
+                    None => buffer.clear(),
+                }
+            }
+            Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
+                return Err(Error::Parser(NomError::new(
+                    err.input.to_string(),
+                    err.code,
+                )))
+            }
+        }
+    }
+}
+
+impl<S> EventStreamProjection<'_, S> {
+    /// Yield `error`, first yielding the data of the event it cuts off if enabled. The stream ends
+    /// after a limit error, as the rest of the oversized line or event cannot be told apart from
+    /// the start of the next one
+    fn fail(&mut self, error: Error) -> Poll<Option<Result<Event, Error>>> {
+        if matches!(error, Error::FieldTooLarge | Error::BufferOverflow) {
+            self.buffer.clear();
+            *self.is_terminated = true;
+        }
+        // an invalid `retry` field does not cut off the event
+        let report = *self.report_partial_events && !matches!(error, Error::InvalidRetryField(_));
+        let error = with_partial_event(self.builder, report, self.pending_error, Some(error));
+        Poll::Ready(error.map(Err))
+    }
+}
+
+impl<S> Stream for EventStream<S>
+where
+    S: Stream<Item = Result<Bytes, ReqwestError>>,
+{
+    type Item = Result<Event, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        if let Some(err) = this.pending_error.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+
+        match parse_event(
+            this.buffer,
+            this.builder,
+            *this.max_field_size,
+            *this.lenient,
+            *this.report_invalid_retry,
+        ) {
+            Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+            Err(err) => return this.fail(err),
+            _ => {}
+        }
+
+        if *this.is_terminated {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(bytes))) => {
+                    let string = if *this.lenient {
+                        decode_utf8_lossy(this.utf8_buffer, &bytes)
+                    } else {
+                        match decode_utf8(this.utf8_buffer, &bytes) {
+                            Ok(string) => string,
+                            Err(err) => return this.fail(Error::Utf8(err)),
+                        }
+                    };
+                    if string.is_empty() {
+                        continue;
+                    }
+
+                    let slice = if *this.is_started {
+                        &string
+                    } else {
+                        *this.is_started = true;
+                        string.strip_prefix(is_bom).unwrap_or(&string)
+                    };
+                    this.buffer.push_str(slice);
+
+                    match parse_event(
+                        this.buffer,
+                        this.builder,
+                        *this.max_field_size,
+                        *this.lenient,
+                        *this.report_invalid_retry,
+                    ) {
+                        Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                        Err(err) => return this.fail(err),
+                        _ => {}
+                    }
+
+                    // what is left is an incomplete line
+                    if let Some(max_field_size) = *this.max_field_size {
+                        if field_size(this.buffer) > max_field_size {
+                            return this.fail(Error::FieldTooLarge);
+                        }
+                    }
+                    if let Some(max_event_size) = *this.max_event_size {
+                        if this.buffer.len() + this.builder.event.data.len() > max_event_size {
+                            return this.fail(Error::BufferOverflow);
+                        }
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    let err = with_partial_event(
+                        this.builder,
+                        *this.report_partial_events,
+                        this.pending_error,
+                        Some(Error::Transport(err)),
+                    );
+                    return Poll::Ready(err.map(Err));
+                }
+                Poll::Ready(None) if *this.lenient => {
+                    *this.is_terminated = true;
+                    if !this.utf8_buffer.is_empty() {
+                        this.utf8_buffer.clear();
+                        this.buffer.push(char::REPLACEMENT_CHARACTER);
+                    }
+                    // dispatch an event the server did not terminate with a blank line
+                    this.buffer.push_str("\n\n");
+                    return match parse_event(
+                        this.buffer,
+                        this.builder,
+                        None,
+                        true,
+                        *this.report_invalid_retry,
+                    ) {
+                        Ok(Some(event)) => Poll::Ready(Some(Ok(event))),
+                        Err(err) => this.fail(err),
+                        Ok(None) => Poll::Ready(None),
+                    };
+                }
+                Poll::Ready(None) => {
+                    *this.is_terminated = true;
+                    // a CR at the very end is held back in case an LF follows, but still ends
+                    // its line
+                    if this.buffer.ends_with('\r') {
+                        this.buffer.push('\n');
+                        match parse_event(
+                            this.buffer,
+                            this.builder,
+                            *this.max_field_size,
+                            false,
+                            *this.report_invalid_retry,
+                        ) {
+                            Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                            Err(err) => return this.fail(err),
+                            Ok(None) => {}
+                        }
+                    }
+                    let mut err = None;
+                    if !this.utf8_buffer.is_empty() {
+                        if let Err(utf8_err) = String::from_utf8(core::mem::take(this.utf8_buffer))
+                        {
+                            err = Some(Error::Utf8(utf8_err));
+                        }
+                    }
+                    let err = with_partial_event(
+                        this.builder,
+                        *this.report_partial_events,
+                        this.pending_error,
+                        err,
+                    );
+                    return Poll::Ready(err.map(Err));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::stream::{self, StreamExt};
+    use std::time::Duration;
+
+    fn event_stream(
+        chunks: &[&[u8]],
+    ) -> EventStream<impl Stream<Item = Result<Bytes, ReqwestError>>> {
+        let chunks: Vec<Result<Bytes, ReqwestError>> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        EventStream::new(stream::iter(chunks))
+    }
+
+    fn messages(chunks: &[&[u8]]) -> Vec<MessageEvent> {
+        block_on(event_stream(chunks).collect::<Vec<_>>())
+            .into_iter()
+            .filter_map(|item| item.unwrap().into_message())
+            .collect()
+    }
+
+    fn data(chunks: &[&[u8]]) -> Vec<String> {
+        messages(chunks)
+            .into_iter()
+            .map(|message| message.data)
+            .collect()
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(data(&[b"data: a\n\n"]), ["a"]);
+        assert_eq!(data(&[b"data: a\r\r"]), ["a"]);
+        assert_eq!(data(&[b"data: a\r\n\r\n"]), ["a"]);
+        assert_eq!(
+            data(&[b"data: a\rdata: b\n\ndata: c\r\n\r\n"]),
+            ["a\nb", "c"]
+        );
+    }
+
+    #[test]
+    fn crlf_split_across_chunks() {
+        assert_eq!(
+            data(&[b"data: a\r", b"\ndata: b\r", b"\n\r", b"\n"]),
+            ["a\nb"]
+        );
+        assert_eq!(data(&[b"data: a\r\n\r", b"\ndata: b\n\n"]), ["a", "b"]);
+    }
+
+    #[test]
+    fn utf8_split_across_chunks() {
+        let bytes = "data: é€\n\n".as_bytes();
+        let (first, rest) = bytes.split_at(7);
+        let (second, third) = rest.split_at(2);
+        assert_eq!(data(&[first, second, third]), ["é€"]);
+    }
+
+    #[test]
+    fn bom_split_across_chunks() {
+        assert_eq!(data(&[b"\xEF\xBB", b"\xBFdata: a\n\n"]), ["a"]);
+        assert_eq!(data(&[b"\xEF", b"\xBB\xBF", b"data: a\n\n"]), ["a"]);
+        // only a leading BOM is stripped
+        assert_eq!(data(&[b"data: a\n\n\xEF\xBB\xBFdata: b\n\n"]).len(), 1);
+    }
+
+    #[test]
+    fn empty_id_resets_last_event_id() {
+        let ids: Vec<String> =
+            messages(&[b"id: 1\ndata: a\n\ndata: b\n\nid\ndata: c\n\nid:\ndata: d\n\n"])
+                .into_iter()
+                .map(|message| message.id)
+                .collect();
+        assert_eq!(ids, ["1", "1", "", ""]);
+    }
+
+    #[test]
+    fn retry_only_event() {
+        let mut stream = event_stream(&[b"retry: 500\n\n"]);
+        assert!(block_on(stream.next()).is_none());
+        assert_eq!(
+            stream.take_reconnection_time(),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(stream.take_reconnection_time(), None);
+
+        let messages = messages(&[b"retry: 500\ndata: a\n\n"]);
+        assert_eq!(messages[0].retry, Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn max_event_size() {
+        let mut stream = event_stream(&[b"data: 0123", b"456789"]);
+        stream.set_max_event_size(Some(12));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::BufferOverflow))
+        ));
+
+        let mut stream = event_stream(&[b"data: 0123\n", b"data: 456789"]);
+        stream.set_max_event_size(Some(16));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::BufferOverflow))
+        ));
+
+        let mut stream = event_stream(&[b"data: 0123\n\n"]);
+        stream.set_max_event_size(Some(16));
+        assert_eq!(
+            block_on(stream.next()).unwrap().unwrap().data(),
+            Some("0123")
+        );
+    }
+
+    #[test]
+    fn limit_errors_end_the_stream() {
+        let mut stream = event_stream(&[b"data: 0123456789\n", b"data: a\n\n"]);
+        stream.set_max_field_size(Some(8));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::FieldTooLarge))
+        ));
+        assert!(block_on(stream.next()).is_none());
+
+        let mut stream = event_stream(&[b"data: 0123", b"456789", b"\n\ndata: a\n\n"]);
+        stream.set_max_field_size(Some(8));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::FieldTooLarge))
+        ));
+        assert!(block_on(stream.next()).is_none());
+
+        let mut stream = event_stream(&[b"data: 0123", b"456789", b"\n\ndata: a\n\n"]);
+        stream.set_max_event_size(Some(12));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::BufferOverflow))
+        ));
+        assert!(block_on(stream.next()).is_none());
+    }
+
+    #[test]
+    fn partial_event_before_errors() {
+        let mut stream = event_stream(&[b"data: a\n", b"data: \xFF\n\n"]);
+        stream.set_report_partial_events(true);
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::PartialEvent(data))) if data == "a"
+        ));
+        assert!(matches!(block_on(stream.next()), Some(Err(Error::Utf8(_)))));
+
+        let mut stream = event_stream(&[b"data: a\n", b"data: 0123456789"]);
+        stream.set_report_partial_events(true);
+        stream.set_max_field_size(Some(8));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::PartialEvent(data))) if data == "a"
+        ));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::FieldTooLarge))
+        ));
+        assert!(block_on(stream.next()).is_none());
+
+        let mut stream = event_stream(&[b"data: a\n", b"data: 0123456789"]);
+        stream.set_report_partial_events(true);
+        stream.set_max_event_size(Some(12));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::PartialEvent(data))) if data == "a"
+        ));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Err(Error::BufferOverflow))
+        ));
+        assert!(block_on(stream.next()).is_none());
+    }
+}
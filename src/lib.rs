@@ -1,17 +1,19 @@
 //! Provides a simple wrapper for [`reqwest`] to provide an Event Source implementation.
 //! You can learn more about Server Sent Events (SSE) take a look at [the MDN
 //! docs](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events/Using_server-sent_events)
-//! This crate uses [`eventsource_stream`] to wrap the underlying Bytes stream, and retries failed
+//! This crate parses the underlying Bytes stream with its own [`EventStream`], and retries failed
 //! requests.
 //!
 //! # Example
 //!
 //! ```ignore
-//! let mut es = EventSource::get("http://localhost:8000/events");
+//! let mut es = EventSource::get("http://localhost:8000/events")?;
 //! while let Some(event) = es.next().await {
 //!     match event {
-//!         Ok(Event::Open) => println!("Connection Open!"),
+//!         Ok(Event::Open(_)) => println!("Connection Open!"),
 //!         Ok(Event::Message(message)) => println!("Message: {:#?}", message),
+//!         Ok(Event::Closed) => println!("Connection Closed!"),
+//!         Ok(Event::Comment(_)) => {}
 //!         Err(err) => {
 //!             println!("Error: {}", err);
 //!             es.close();
@@ -19,15 +21,80 @@
 //!     }
 //! }
 //! ```
+//!
+#![cfg_attr(
+    feature = "event-source",
+    doc = "# Cookies\n\n\
+           Reconnects resend the original request, so cookies set by the server, e.g. for sticky \
+           sessions, are only sent back if the [`reqwest::Client`] stores them. The client created \
+           by [`EventSource::get`] and [`EventSource::post`] does not, so build one with \
+           `cookie_store(true)` (requires the `cookies` feature of `reqwest`) and pass it to \
+           [`EventSource::get_with_client`] or [`EventSource::post_with_client`].\n"
+)]
+//! # Features
+//!
+//! The `event-source` feature, enabled by default, provides the `EventSource`. Without it, the
+//! [`Event`] and [`enum@Error`] types, the [`retry`] policies and the [`EventStream`] parser can be
+//! used on their own, e.g. to manage connections yourself.
+//!
+#![cfg_attr(
+    feature = "event-source",
+    doc = "The [`EventSource`] needs a timer, which is \
+           [`futures-timer`](https://docs.rs/futures-timer) by default. The `tokio-timer` and \
+           `async-io` features use the timer of `tokio` or `async-io` (as used by `async-std` and \
+           `smol`) instead, and are best combined with `default-features = false` to not pull in \
+           `futures-timer`.\n\n\
+           The `metrics` feature records the `sse_events_total`, `sse_reconnects_total` and \
+           `sse_errors_total` (labeled by `kind`) counters and the `sse_connect_duration_seconds` \
+           histogram of every [`EventSource`] with the [`metrics`](https://docs.rs/metrics) \
+           crate.\n"
+)]
+//! The `gzip`, `brotli`, `deflate` and `zstd` features enable the matching decompression in
+//! reqwest, so the stream is parsed after it is decompressed. A stream compressed with an encoding
+//! that is not enabled fails with [`Error::UnsupportedContentEncoding`] instead of being parsed.
 
 #[macro_use]
 extern crate thiserror;
 
+#[cfg(feature = "event-source")]
+#[macro_use]
+mod macros;
+
+#[cfg(feature = "event-source")]
+pub mod adapters;
+#[cfg(feature = "event-source")]
+mod builder;
 mod error;
+mod event;
+#[cfg(feature = "event-source")]
 mod event_source;
+mod event_stream;
+mod parser;
+#[cfg(feature = "event-source")]
+mod replay;
+#[cfg(feature = "event-source")]
 mod reqwest_ext;
+#[cfg(feature = "event-source")]
+mod resilient;
 pub mod retry;
+#[cfg(feature = "event-source")]
+mod telemetry;
+#[cfg(test)]
+#[cfg_attr(not(feature = "event-source"), allow(dead_code))]
+mod testing;
+mod timer;
 
-pub use error::{CannotCloneRequestError, Error};
-pub use event_source::{Event, EventSource, ReadyState};
+#[cfg(feature = "event-source")]
+pub use builder::EventSourceBuilder;
+pub use error::{CannotCloneRequestError, CloneableError, Error};
+pub use event::{Event, MessageEvent, OpenEvent, SimpleEvent};
+#[cfg(feature = "event-source")]
+pub use event_source::{CloseReason, EventSource, ReadyState, Stats, StatusAction};
+pub use event_stream::EventStream;
+#[cfg(feature = "event-source")]
+pub use replay::Replay;
+#[cfg(feature = "event-source")]
 pub use reqwest_ext::RequestBuilderExt;
+#[cfg(feature = "event-source")]
+pub use resilient::ResilientEventSource;
+pub use timer::{Clock, Sleep};
@@ -0,0 +1,17 @@
+/// Emit a [`tracing`](https://docs.rs/tracing) event at debug level when the `tracing` feature is
+/// enabled, and expand to nothing otherwise
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+/// Emit a [`tracing`](https://docs.rs/tracing) event at trace level when the `tracing` feature is
+/// enabled, and expand to nothing otherwise
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
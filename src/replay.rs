@@ -0,0 +1,108 @@
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_source::ReadyState;
+use core::pin::Pin;
+use futures_core::stream::{FusedStream, Stream};
+use futures_core::task::{Context, Poll};
+use pin_project_lite::pin_project;
+
+#[cfg(doc)]
+use crate::event_source::EventSource;
+
+pin_project! {
+/// A [`Stream`] yielding the same items as an [`EventSource`], but taken from another stream
+/// instead of an HTTP connection, e.g. to test event handling or replay a recorded stream.
+///
+/// Like an [`EventSource`], it tracks its [`ReadyState`] and last event id from the events it
+/// yields, can be closed, and yields a single [`Event::Closed`] before ending.
+pub struct Replay<S> {
+    #[pin]
+    stream: S,
+    ready_state: ReadyState,
+    closed_emitted: bool,
+    last_event_id: String,
+}
+}
+
+impl<S> Replay<S> {
+    /// Replay the items of `stream`
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            ready_state: ReadyState::Connecting,
+            closed_emitted: false,
+            last_event_id: String::new(),
+        }
+    }
+
+    /// Close the stream. The next item is an [`Event::Closed`], then the stream ends
+    pub fn close(&mut self) {
+        self.ready_state = ReadyState::Closed;
+    }
+
+    /// Get the ready state, which is `Open` after an [`Event::Open`] and `Closed` after an
+    /// [`Event::Closed`] or when closed
+    pub fn ready_state(&self) -> ReadyState {
+        self.ready_state
+    }
+
+    /// Get the id of the last message, or an empty string if there was none
+    pub fn last_event_id(&self) -> &str {
+        &self.last_event_id
+    }
+
+    /// Get back the underlying stream
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> Stream for Replay<S>
+where
+    S: Stream<Item = Result<Event, Error>>,
+{
+    type Item = Result<Event, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        if *this.ready_state == ReadyState::Closed {
+            if *this.closed_emitted {
+                return Poll::Ready(None);
+            }
+            *this.closed_emitted = true;
+            return Poll::Ready(Some(Ok(Event::Closed)));
+        }
+
+        match this.stream.poll_next(cx) {
+            Poll::Ready(Some(Ok(event))) => {
+                match &event {
+                    Event::Open(_) => *this.ready_state = ReadyState::Open,
+                    Event::Message(message) => *this.last_event_id = message.id.clone(),
+                    Event::Closed => {
+                        *this.ready_state = ReadyState::Closed;
+                        *this.closed_emitted = true;
+                    }
+                    Event::Comment(_) => {}
+                }
+                Poll::Ready(Some(Ok(event)))
+            }
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err))),
+            Poll::Ready(None) => {
+                *this.ready_state = ReadyState::Closed;
+                *this.closed_emitted = true;
+                Poll::Ready(Some(Ok(Event::Closed)))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S> FusedStream for Replay<S>
+where
+    S: Stream<Item = Result<Event, Error>>,
+{
+    fn is_terminated(&self) -> bool {
+        self.ready_state == ReadyState::Closed && self.closed_emitted
+    }
+}
@@ -0,0 +1,241 @@
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_source::{BoxedRetry, CloseReason, EventSource};
+use crate::retry::RetryPolicy;
+use crate::timer::Timeout;
+#[cfg(doc)]
+use crate::EventSourceBuilder;
+use core::future::Future;
+use core::pin::Pin;
+use futures_core::ready;
+use futures_core::stream::{FusedStream, Stream};
+use futures_core::task::{Context, Poll};
+use std::time::Duration;
+
+/// A [`Stream`] of the items of an [`EventSource`] that is rebuilt from scratch when it closes,
+/// e.g. to resolve the host again or use a new client once the retry policy gave up.
+///
+/// When the current EventSource closes for any reason other than [`ResilientEventSource::close`],
+/// [`EventSource::close`] or its [`EventSourceBuilder::max_lifetime`] elapsing, its
+/// [`Event::Closed`] is not yielded. Instead, a new EventSource is created by the factory after the
+/// cool-down and continues from the last event id of the old one. The stream only ends after it is
+/// closed. Prefer [`ResilientEventSource::close`] over closing the EventSource through
+/// [`ResilientEventSource::get_mut`], which is not noticed once it already closed after an error
+pub struct ResilientEventSource<F, R = BoxedRetry> {
+    factory: F,
+    cool_down: Duration,
+    source: EventSource<R>,
+    delay: Option<Timeout>,
+    closed: bool,
+    closed_pending: bool,
+    rebuilds: usize,
+}
+
+impl<F, R> ResilientEventSource<F, R>
+where
+    F: FnMut() -> EventSource<R>,
+{
+    /// Create the first EventSource with `factory`, and a new one `cool_down` after each one
+    /// closed
+    pub fn new(mut factory: F, cool_down: Duration) -> Self {
+        let source = factory();
+        Self {
+            factory,
+            cool_down,
+            source,
+            delay: None,
+            closed: false,
+            closed_pending: false,
+            rebuilds: 0,
+        }
+    }
+}
+
+impl<F, R: RetryPolicy> ResilientEventSource<F, R> {
+    /// Close the current EventSource without creating a new one. The next item is an
+    /// [`Event::Closed`], then the stream ends
+    pub fn close(&mut self) {
+        self.closed = true;
+        // the closed EventSource waiting to be rebuilt already yielded its `Event::Closed`
+        if self.delay.take().is_some() {
+            self.closed_pending = true;
+        }
+        self.source.close();
+    }
+
+    /// Get a reference to the current [`EventSource`]
+    pub fn get_ref(&self) -> &EventSource<R> {
+        &self.source
+    }
+
+    /// Get a mutable reference to the current [`EventSource`]
+    pub fn get_mut(&mut self) -> &mut EventSource<R> {
+        &mut self.source
+    }
+
+    /// Get the number of times the EventSource was created again
+    pub fn rebuilds(&self) -> usize {
+        self.rebuilds
+    }
+}
+
+impl<F, R> Stream for ResilientEventSource<F, R>
+where
+    F: FnMut() -> EventSource<R> + Unpin,
+    R: RetryPolicy,
+{
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.closed_pending {
+            self.closed_pending = false;
+            return Poll::Ready(Some(Ok(Event::Closed)));
+        }
+        loop {
+            if let Some(delay) = self.delay.as_mut() {
+                ready!(Pin::new(delay).poll(cx));
+                self.delay = None;
+                let mut source = (self.factory)();
+                if source
+                    .set_last_event_id_bytes(self.source.last_event_id_bytes())
+                    .is_err()
+                {
+                    // fail the first request of the new EventSource in the same way
+                    source.set_last_event_id(self.source.last_event_id());
+                }
+                self.source = source;
+                self.rebuilds += 1;
+            }
+            match ready!(Pin::new(&mut self.source).poll_next(cx)) {
+                Some(Ok(Event::Closed))
+                    if !self.closed
+                        && !matches!(
+                            self.source.close_reason(),
+                            Some(CloseReason::UserClosed | CloseReason::Expired)
+                        ) =>
+                {
+                    let delay = self.source.clock().delay(self.cool_down);
+                    self.delay = Some(delay);
+                }
+                item => return Poll::Ready(item),
+            }
+        }
+    }
+}
+
+impl<F, R> FusedStream for ResilientEventSource<F, R>
+where
+    F: FnMut() -> EventSource<R> + Unpin,
+    R: RetryPolicy,
+{
+    fn is_terminated(&self) -> bool {
+        !self.closed_pending && self.delay.is_none() && self.source.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{next_data, MockClock, Reply, Server};
+    use futures::StreamExt;
+    use reqwest::StatusCode;
+
+    #[tokio::test]
+    async fn close_after_error() {
+        let server = Server::new(|_| Reply::status(500));
+        let url = server.url().to_string();
+        let mut source = ResilientEventSource::new(
+            move || EventSource::get(&url).unwrap().without_retries(),
+            Duration::ZERO,
+        );
+        assert!(matches!(
+            source.next().await,
+            Some(Err(Error::InvalidStatusCode(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                _
+            )))
+        ));
+        source.close();
+        assert!(matches!(source.next().await, Some(Ok(Event::Closed))));
+        assert!(source.next().await.is_none());
+        assert_eq!(source.rebuilds(), 0);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn close_while_cooling_down() {
+        let clock = MockClock::new();
+        let server = Server::new(|_| Reply::status(500));
+        let url = server.url().to_string();
+        let mut source = ResilientEventSource::new(
+            move || {
+                EventSource::builder(reqwest::Client::new().get(&url))
+                    .clock(clock.clone())
+                    .build()
+                    .unwrap()
+                    .without_retries()
+            },
+            Duration::from_secs(1),
+        );
+        assert!(source.next().await.unwrap().is_err());
+        // the first EventSource closes and the cool-down starts, with nothing to yield
+        futures::future::poll_fn(|cx| {
+            assert!(Pin::new(&mut source).poll_next(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        source.close();
+        assert!(matches!(source.next().await, Some(Ok(Event::Closed))));
+        assert!(source.next().await.is_none());
+        assert_eq!(source.rebuilds(), 0);
+    }
+
+    #[tokio::test]
+    async fn rebuild_on_fatal() {
+        let server = Server::new(|n| match n {
+            0 => Reply::status(401),
+            1 => Reply::events("id: 7\ndata: a\n\n"),
+            _ => Reply::events("data: b\n\n").hold(),
+        });
+        let url = server.url().to_string();
+        let mut source = ResilientEventSource::new(
+            move || EventSource::get(&url).unwrap().without_retries(),
+            Duration::ZERO,
+        );
+        assert!(source.next().await.unwrap().is_err());
+        assert_eq!(source.get_ref().close_reason(), Some(CloseReason::Fatal));
+        assert_eq!(next_data(&mut source).await, "a");
+        assert_eq!(source.rebuilds(), 1);
+        // giving up after the stream ended rebuilds the EventSource as well, resuming from the
+        // last event id
+        assert!(matches!(source.next().await, Some(Err(Error::StreamEnded))));
+        assert_eq!(next_data(&mut source).await, "b");
+        assert_eq!(source.rebuilds(), 2);
+        assert!(!server.requests()[1].contains("last-event-id"));
+        assert!(server.requests()[2].contains("last-event-id: 7\r\n"));
+    }
+
+    #[tokio::test]
+    async fn no_rebuild_on_expired() {
+        let clock = MockClock::new();
+        let server = Server::new(|_| Reply::events("data: a\n\n").hold());
+        let url = server.url().to_string();
+        let factory_clock = clock.clone();
+        let mut source = ResilientEventSource::new(
+            move || {
+                EventSource::builder(reqwest::Client::new().get(&url))
+                    .max_lifetime(Duration::from_secs(1))
+                    .clock(factory_clock.clone())
+                    .build()
+                    .unwrap()
+            },
+            Duration::ZERO,
+        );
+        assert_eq!(next_data(&mut source).await, "a");
+        clock.advance(Duration::from_secs(1));
+        assert!(matches!(source.next().await, Some(Ok(Event::Closed))));
+        assert_eq!(source.get_ref().close_reason(), Some(CloseReason::Expired));
+        assert!(source.next().await.is_none());
+        assert_eq!(source.rebuilds(), 0);
+    }
+}
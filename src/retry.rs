@@ -1,19 +1,50 @@
 //! Helpers to handle connection delays when receiving errors
 
 use crate::error::Error;
+use crate::timer::{Clock, Instant, SharedClock};
+#[cfg(feature = "jitter")]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::cell::Cell;
+#[cfg(feature = "jitter")]
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Duration;
 
 #[cfg(doc)]
-use crate::event_source::{Event, EventSource};
+use crate::event::Event;
 
-/// Describes how an [`EventSource`] should retry on receiving an [`enum@Error`]
+/// Describes how an `EventSource` should retry on receiving an [`enum@Error`]
 pub trait RetryPolicy {
     /// Submit a new retry delay based on the [`enum@Error`], last retry number and duration, if
-    /// available. A policy may also return `None` if it does not want to retry
+    /// available. A policy may also return `None` if it does not want to retry. The built-in
+    /// policies give up on errors that would fail the same way again, such as an
+    /// [`Error::Transport`] for an invalid url. Rejected responses are passed as an
+    /// [`Error::InvalidStatusCode`] with their status, so a policy can e.g. retry a
+    /// `503 Service Unavailable` but not a `401 Unauthorized`
     fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration>;
 
-    /// Set a new reconnection time if received from an [`Event`]
+    /// Set a new reconnection time if received from an [`Event`]. This is called by the
+    /// `EventSource` whenever the server sends a `retry:` field, and the built-in policies use it
+    /// as the delay for the next reconnect
     fn set_reconnection_time(&mut self, duration: Duration);
+
+    /// Observe a successful connection, e.g. to adapt the delays of an adaptive policy. This is
+    /// called by the `EventSource` once per opened connection, with the retry that opened it or
+    /// `None` if no retry was needed. Does nothing by default
+    fn on_success(&mut self, _last_retry: Option<(usize, Duration)>) {}
+}
+
+impl<P: RetryPolicy + ?Sized> RetryPolicy for Box<P> {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        (**self).retry(error, last_retry)
+    }
+    fn set_reconnection_time(&mut self, duration: Duration) {
+        (**self).set_reconnection_time(duration)
+    }
+    fn on_success(&mut self, last_retry: Option<(usize, Duration)>) {
+        (**self).on_success(last_retry)
+    }
 }
 
 /// A [`RetryPolicy`] which backs off exponentially
@@ -47,10 +78,16 @@ impl ExponentialBackoff {
 }
 
 impl RetryPolicy for ExponentialBackoff {
-    fn retry(&self, _error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        if !error.is_retryable() {
+            return None;
+        }
         if let Some((retry_num, last_duration)) = last_retry {
             if self.max_retries.is_none() || retry_num < self.max_retries.unwrap() {
-                let duration = last_duration.mul_f64(self.factor);
+                // saturate instead of panicking like `Duration::mul_f64` does on overflow
+                let duration =
+                    Duration::try_from_secs_f64(last_duration.as_secs_f64() * self.factor)
+                        .unwrap_or(Duration::MAX);
                 if let Some(max_duration) = self.max_duration {
                     Some(duration.min(max_duration))
                 } else {
@@ -71,6 +108,44 @@ impl RetryPolicy for ExponentialBackoff {
     }
 }
 
+/// A [`RetryPolicy`] which backs off following the Fibonacci sequence, `base × Fibonacci(retry
+/// number)`, which grows more gently than doubling
+#[derive(Debug, Clone)]
+pub struct FibonacciBackoff {
+    /// The delay of the first two retries
+    pub base: Duration,
+    /// The maximum duration to delay
+    pub max_duration: Duration,
+}
+
+impl FibonacciBackoff {
+    /// Create a new Fibonacci backoff retry policy
+    pub const fn new(base: Duration, max_duration: Duration) -> Self {
+        Self { base, max_duration }
+    }
+}
+
+impl RetryPolicy for FibonacciBackoff {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        if !error.is_retryable() {
+            return None;
+        }
+        let retry_num = last_retry.map(|retry| retry.0 + 1).unwrap_or(1);
+        let (mut fib, mut next) = (1u32, 1u32);
+        for _ in 1..retry_num {
+            (fib, next) = (next, fib.saturating_add(next));
+            if self.base.saturating_mul(fib) >= self.max_duration {
+                break;
+            }
+        }
+        Some(self.base.saturating_mul(fib).min(self.max_duration))
+    }
+    fn set_reconnection_time(&mut self, duration: Duration) {
+        self.base = duration;
+        self.max_duration = self.max_duration.max(duration);
+    }
+}
+
 /// A [`RetryPolicy`] which always emits the same delay
 #[derive(Debug, Clone)]
 pub struct Constant {
@@ -85,10 +160,25 @@ impl Constant {
     pub const fn new(delay: Duration, max_retries: Option<usize>) -> Self {
         Self { delay, max_retries }
     }
+
+    /// Change the delay, e.g. to reconnect less often under load
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+}
+
+impl From<Duration> for Constant {
+    /// Create a constant retry policy retrying forever with the given delay
+    fn from(delay: Duration) -> Self {
+        Self::new(delay, None)
+    }
 }
 
 impl RetryPolicy for Constant {
-    fn retry(&self, _error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        if !error.is_retryable() {
+            return None;
+        }
         if let Some((retry_num, _)) = last_retry {
             if self.max_retries.is_none() || retry_num < self.max_retries.unwrap() {
                 Some(self.delay)
@@ -115,10 +205,363 @@ impl RetryPolicy for Never {
     fn set_reconnection_time(&mut self, _duration: Duration) {}
 }
 
-/// The default [`RetryPolicy`] when initializing an [`EventSource`]
+/// A [`RetryPolicy`] which gives up after a maximum number of consecutive retries, taking its
+/// delays from another policy
+#[derive(Debug, Clone)]
+pub struct MaxRetries<P> {
+    /// The policy providing the delays
+    pub inner: P,
+    /// The maximum number of consecutive retries before giving up
+    pub max_retries: usize,
+    retries: Cell<usize>,
+}
+
+impl<P> MaxRetries<P> {
+    /// Create a new retry policy giving up after `max_retries` consecutive retries
+    pub const fn new(inner: P, max_retries: usize) -> Self {
+        Self {
+            inner,
+            max_retries,
+            retries: Cell::new(0),
+        }
+    }
+
+    /// Get the number of retries left before giving up
+    pub fn remaining(&self) -> usize {
+        self.max_retries.saturating_sub(self.retries.get())
+    }
+}
+
+impl<P: RetryPolicy> RetryPolicy for MaxRetries<P> {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        let retry_num = last_retry.map(|retry| retry.0).unwrap_or(0);
+        self.retries.set(retry_num);
+        if retry_num >= self.max_retries {
+            return None;
+        }
+        let delay = self.inner.retry(error, last_retry);
+        if delay.is_some() {
+            self.retries.set(retry_num + 1);
+        }
+        delay
+    }
+    fn set_reconnection_time(&mut self, duration: Duration) {
+        self.inner.set_reconnection_time(duration)
+    }
+    fn on_success(&mut self, last_retry: Option<(usize, Duration)>) {
+        self.retries.set(0);
+        self.inner.on_success(last_retry)
+    }
+}
+
+/// A [`RetryPolicy`] which gives up once a time budget has elapsed since the first of a series of
+/// consecutive failures, taking its delays from another policy. The budget starts over when a
+/// connection succeeds
+#[derive(Debug, Clone)]
+pub struct TimeBudget<P> {
+    /// The policy providing the delays
+    pub inner: P,
+    /// How long to keep retrying after the first failure
+    pub budget: Duration,
+    first_failure: Cell<Option<Instant>>,
+    clock: SharedClock,
+}
+
+impl<P> TimeBudget<P> {
+    /// Create a new retry policy giving up once `budget` has elapsed since the first failure
+    pub const fn new(inner: P, budget: Duration) -> Self {
+        Self {
+            inner,
+            budget,
+            first_failure: Cell::new(None),
+            clock: SharedClock::system(),
+        }
+    }
+
+    /// Measure the budget with `clock` instead of the system time, e.g. a mock clock in tests
+    pub fn with_clock<C: Clock + Send + Sync + 'static>(mut self, clock: C) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+}
+
+impl<P: RetryPolicy> RetryPolicy for TimeBudget<P> {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        // the retry state is cleared when a connection succeeds, so this is a new series
+        let first_failure = match (last_retry, self.first_failure.get()) {
+            (Some(_), Some(first_failure)) => first_failure,
+            _ => {
+                let now = self.clock.now();
+                self.first_failure.set(Some(now));
+                now
+            }
+        };
+        if self.clock.now().saturating_duration_since(first_failure) >= self.budget {
+            return None;
+        }
+        self.inner.retry(error, last_retry)
+    }
+    fn set_reconnection_time(&mut self, duration: Duration) {
+        self.inner.set_reconnection_time(duration)
+    }
+    fn on_success(&mut self, last_retry: Option<(usize, Duration)>) {
+        self.first_failure.set(None);
+        self.inner.on_success(last_retry)
+    }
+}
+
+/// A token bucket shared by [`RateLimited`] policies to limit how many reconnects happen at once
+/// across several `EventSource`s, e.g. after an outage of their server. It allows a burst of
+/// `max_reconnects`, then one reconnect every `interval / max_reconnects`. Clones share the same
+/// bucket
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    emission_interval: Duration,
+    burst_tolerance: Duration,
+    next_reconnect: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimiter {
+    /// Create a bucket allowing `max_reconnects` reconnects per `interval`
+    pub fn new(max_reconnects: usize, interval: Duration) -> Self {
+        let max_reconnects = u32::try_from(max_reconnects.max(1)).unwrap_or(u32::MAX);
+        let emission_interval = interval / max_reconnects;
+        Self {
+            emission_interval,
+            burst_tolerance: emission_interval * (max_reconnects - 1),
+            next_reconnect: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Reserve a reconnect at `at` or later, returning when it may happen
+    fn reserve(&self, at: Instant) -> Instant {
+        let mut next_reconnect = self
+            .next_reconnect
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let next = next_reconnect.unwrap_or(at);
+        let allowed = match next.checked_sub(self.burst_tolerance) {
+            Some(earliest) => earliest.max(at),
+            None => at,
+        };
+        *next_reconnect = Some(next.max(allowed) + self.emission_interval);
+        allowed
+    }
+}
+
+/// A [`RetryPolicy`] which takes its delays from another policy and lengthens them as needed to
+/// respect a [`RateLimiter`] shared with other policies
+#[derive(Debug, Clone)]
+pub struct RateLimited<P> {
+    /// The policy providing the delays
+    pub inner: P,
+    limiter: RateLimiter,
+    clock: SharedClock,
+}
+
+impl<P> RateLimited<P> {
+    /// Create a new retry policy whose reconnects are limited by `limiter`
+    pub const fn new(inner: P, limiter: RateLimiter) -> Self {
+        Self {
+            inner,
+            limiter,
+            clock: SharedClock::system(),
+        }
+    }
+
+    /// Read the time of the reconnects from `clock` instead of the system time, e.g. a mock clock
+    /// in tests. Policies sharing a [`RateLimiter`] should use the same clock
+    pub fn with_clock<C: Clock + Send + Sync + 'static>(mut self, clock: C) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+}
+
+impl<P: RetryPolicy> RetryPolicy for RateLimited<P> {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        let delay = self.inner.retry(error, last_retry)?;
+        let now = self.clock.now();
+        // a delay too large for an `Instant` is far beyond any rate limit
+        match now.checked_add(delay) {
+            Some(at) => Some(self.limiter.reserve(at).saturating_duration_since(now)),
+            None => Some(delay),
+        }
+    }
+    fn set_reconnection_time(&mut self, duration: Duration) {
+        self.inner.set_reconnection_time(duration)
+    }
+    fn on_success(&mut self, last_retry: Option<(usize, Duration)>) {
+        self.inner.on_success(last_retry)
+    }
+}
+
+/// A [`RetryPolicy`] which randomizes the delays of another policy by up to ±`fraction` to avoid
+/// many clients reconnecting at the same time. Requires the `jitter` feature
+#[cfg(feature = "jitter")]
+#[derive(Debug)]
+pub struct Jitter<P> {
+    /// The policy providing the delays to randomize
+    pub inner: P,
+    /// The maximum fraction of the delay to add or subtract, between `0.` and `1.`
+    pub fraction: f64,
+    rng: RefCell<StdRng>,
+}
+
+#[cfg(feature = "jitter")]
+impl<P> Jitter<P> {
+    /// Create a new jitter retry policy seeded from the operating system's entropy source
+    pub fn new(inner: P, fraction: f64) -> Self {
+        Self {
+            inner,
+            fraction,
+            rng: RefCell::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Create a new jitter retry policy with a fixed seed, producing a reproducible sequence of
+    /// delays
+    pub fn with_seed(inner: P, fraction: f64, seed: u64) -> Self {
+        Self {
+            inner,
+            fraction,
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+#[cfg(feature = "jitter")]
+impl<P: RetryPolicy> RetryPolicy for Jitter<P> {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        let delay = self.inner.retry(error, last_retry)?;
+        let fraction = self.fraction.min(1.);
+        if fraction.is_nan() || fraction <= 0. {
+            return Some(delay);
+        }
+        let factor = 1. + self.rng.borrow_mut().gen_range(-fraction..=fraction);
+        Some(Duration::try_from_secs_f64(delay.as_secs_f64() * factor).unwrap_or(delay))
+    }
+    fn set_reconnection_time(&mut self, duration: Duration) {
+        self.inner.set_reconnection_time(duration)
+    }
+    fn on_success(&mut self, last_retry: Option<(usize, Duration)>) {
+        self.inner.on_success(last_retry)
+    }
+}
+
+/// A [`RetryPolicy`] implementing "decorrelated jitter" backoff, where each delay is picked at
+/// random between `base` and three times the previous delay, capped at `cap`. This spreads out
+/// reconnects of many clients better than [`ExponentialBackoff`]. Requires the `jitter` feature
+#[cfg(feature = "jitter")]
+#[derive(Debug)]
+pub struct DecorrelatedJitter {
+    /// The minimum delay
+    pub base: Duration,
+    /// The maximum delay
+    pub cap: Duration,
+    rng: RefCell<StdRng>,
+}
+
+#[cfg(feature = "jitter")]
+impl DecorrelatedJitter {
+    /// Create a new decorrelated jitter retry policy seeded from the operating system's entropy
+    /// source
+    pub fn new(base: Duration, cap: Duration) -> Self {
+        Self {
+            base,
+            cap,
+            rng: RefCell::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Create a new decorrelated jitter retry policy with a fixed seed, producing a reproducible
+    /// sequence of delays
+    pub fn with_seed(base: Duration, cap: Duration, seed: u64) -> Self {
+        Self {
+            base,
+            cap,
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+#[cfg(feature = "jitter")]
+impl RetryPolicy for DecorrelatedJitter {
+    fn retry(&self, error: &Error, last_retry: Option<(usize, Duration)>) -> Option<Duration> {
+        if !error.is_retryable() {
+            return None;
+        }
+        let last_duration = last_retry.map(|retry| retry.1).unwrap_or(self.base);
+        let upper = last_duration.saturating_mul(3).max(self.base);
+        let duration = self.rng.borrow_mut().gen_range(self.base..=upper);
+        Some(duration.min(self.cap))
+    }
+    fn set_reconnection_time(&mut self, duration: Duration) {
+        self.base = duration;
+        self.cap = self.cap.max(duration);
+    }
+}
+
+/// The default [`RetryPolicy`] when initializing an `EventSource`
 pub const DEFAULT_RETRY: ExponentialBackoff = ExponentialBackoff::new(
     Duration::from_millis(300),
     2.,
     Some(Duration::from_secs(5)),
     None,
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClock;
+
+    #[test]
+    fn max_retries_remaining_resets_on_success() {
+        let mut policy = MaxRetries::new(Constant::new(Duration::from_secs(1), None), 3);
+        assert_eq!(policy.remaining(), 3);
+        let delay = policy.retry(&Error::StreamEnded, None).unwrap();
+        policy.retry(&Error::StreamEnded, Some((1, delay))).unwrap();
+        assert_eq!(policy.remaining(), 1);
+        policy.on_success(Some((2, delay)));
+        assert_eq!(policy.remaining(), 3);
+    }
+
+    #[test]
+    fn time_budget_with_clock() {
+        let clock = MockClock::new();
+        let delay = Duration::from_secs(1);
+        let policy = TimeBudget::new(Constant::new(delay, None), Duration::from_secs(10))
+            .with_clock(clock.clone());
+        assert_eq!(policy.retry(&Error::StreamEnded, None), Some(delay));
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(
+            policy.retry(&Error::StreamEnded, Some((1, delay))),
+            Some(delay)
+        );
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(policy.retry(&Error::StreamEnded, Some((2, delay))), None);
+    }
+
+    #[test]
+    fn rate_limited_with_clock() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::new(1, Duration::from_secs(10));
+        let delay = Duration::from_secs(1);
+        let a =
+            RateLimited::new(Constant::new(delay, None), limiter.clone()).with_clock(clock.clone());
+        let b = RateLimited::new(Constant::new(delay, None), limiter).with_clock(clock.clone());
+        assert_eq!(a.retry(&Error::StreamEnded, None), Some(delay));
+        assert_eq!(
+            b.retry(&Error::StreamEnded, None),
+            Some(Duration::from_secs(11))
+        );
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(a.retry(&Error::StreamEnded, None), Some(delay));
+    }
+
+    #[test]
+    fn rate_limited_huge_delay() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(10));
+        let policy = RateLimited::new(Constant::new(Duration::MAX, None), limiter);
+        assert_eq!(policy.retry(&Error::StreamEnded, None), Some(Duration::MAX));
+    }
+}
@@ -0,0 +1,63 @@
+//! Records [`metrics`](https://docs.rs/metrics) at the transitions of an [`EventSource`] when the
+//! `metrics` feature is enabled. Without it, these functions do nothing
+
+use crate::error::Error;
+use std::time::Duration;
+
+#[cfg(doc)]
+use crate::event_source::EventSource;
+
+/// A message was received
+#[inline]
+pub(crate) fn event() {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("sse_events_total").increment(1);
+}
+
+/// A reconnect attempt started
+#[inline]
+pub(crate) fn reconnect() {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("sse_reconnects_total").increment(1);
+}
+
+/// A connection was opened after `duration`
+#[inline]
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn connect(duration: Duration) {
+    #[cfg(feature = "metrics")]
+    metrics::histogram!("sse_connect_duration_seconds").record(duration.as_secs_f64());
+}
+
+/// An error was yielded
+#[inline]
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn error(error: &Error) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("sse_errors_total", "kind" => error_kind(error)).increment(1);
+}
+
+#[cfg(feature = "metrics")]
+fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::Utf8(_) => "utf8",
+        Error::Parser(_) => "parser",
+        Error::Transport(_) => "transport",
+        Error::InvalidContentType(..) => "invalid_content_type",
+        Error::MissingContentType(_) => "missing_content_type",
+        Error::UnsupportedContentEncoding(..) => "unsupported_content_encoding",
+        Error::InvalidStatusCode(..) => "invalid_status_code",
+        Error::InvalidLastEventId(_) => "invalid_last_event_id",
+        Error::StreamEnded => "stream_ended",
+        Error::ConnectTimeout => "connect_timeout",
+        Error::IdleTimeout => "idle_timeout",
+        Error::HeartbeatTimeout => "heartbeat_timeout",
+        Error::BufferOverflow => "buffer_overflow",
+        Error::FieldTooLarge => "field_too_large",
+        Error::InvalidRetryField(_) => "invalid_retry_field",
+        Error::PartialEvent(_) => "partial_event",
+        Error::BodyFactory(_) => "body_factory",
+        #[cfg(feature = "json")]
+        Error::Deserialize(_) => "deserialize",
+    }
+}
@@ -0,0 +1,230 @@
+//! Helpers for the tests: a [`Clock`] that only moves when advanced, and a local HTTP server
+//! replying with canned responses
+
+use crate::error::Error;
+use crate::event::Event;
+use crate::timer::{Clock, Instant, Sleep};
+use core::fmt::Debug;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::task::{Poll, Waker};
+use futures::{Stream, StreamExt};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+struct MockTime {
+    now: Instant,
+    sleeps: usize,
+    wakers: Vec<Waker>,
+}
+
+/// A clock which only moves when advanced, waking the sleeps that are due
+#[derive(Clone)]
+pub(crate) struct MockClock(Arc<Mutex<MockTime>>);
+
+impl MockClock {
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(Mutex::new(MockTime {
+            now: Instant::now(),
+            sleeps: 0,
+            wakers: Vec::new(),
+        })))
+    }
+
+    pub(crate) fn advance(&self, duration: Duration) {
+        let mut time = self.0.lock().unwrap();
+        time.now += duration;
+        for waker in time.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// The number of sleeps created so far
+    pub(crate) fn sleeps(&self) -> usize {
+        self.0.lock().unwrap().sleeps
+    }
+
+    /// Wait until `sleeps` sleeps were created, e.g. by a stream polled at the same time, then
+    /// advance the clock by `duration`
+    pub(crate) async fn advance_after(&self, sleeps: usize, duration: Duration) {
+        while self.sleeps() < sleeps {
+            tokio::task::yield_now().await;
+        }
+        self.advance(duration);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().now
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let time = self.0.clone();
+        let deadline = {
+            let mut time = time.lock().unwrap();
+            time.sleeps += 1;
+            time.now.checked_add(duration)
+        };
+        Box::pin(poll_fn(move |cx| {
+            let mut time = time.lock().unwrap();
+            match deadline {
+                Some(deadline) if time.now >= deadline => Poll::Ready(()),
+                _ => {
+                    time.wakers.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }))
+    }
+}
+
+/// A canned response of a [`Server`]
+pub(crate) struct Reply {
+    status: u16,
+    body: &'static str,
+    rest: Option<(Receiver<()>, &'static str)>,
+    hold: bool,
+}
+
+impl Reply {
+    /// An event stream with `body`, which ends after it
+    pub(crate) fn events(body: &'static str) -> Self {
+        Self {
+            status: 200,
+            body,
+            rest: None,
+            hold: false,
+        }
+    }
+
+    /// An empty response with `status`
+    pub(crate) fn status(status: u16) -> Self {
+        Self {
+            status,
+            body: "",
+            rest: None,
+            hold: false,
+        }
+    }
+
+    /// Send `rest` after the body once `gate` receives a message, e.g. after the test advanced
+    /// its clock
+    pub(crate) fn then(mut self, gate: Receiver<()>, rest: &'static str) -> Self {
+        self.rest = Some((gate, rest));
+        self
+    }
+
+    /// Keep the connection open after the body instead of ending the stream
+    pub(crate) fn hold(mut self) -> Self {
+        self.hold = true;
+        self
+    }
+}
+
+/// A local HTTP server answering the `n`th request with the reply for `n`, starting at `0`
+pub(crate) struct Server {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl Server {
+    pub(crate) fn new<F>(reply: F) -> Self
+    where
+        F: Fn(usize) -> Reply + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = requests.clone();
+        thread::spawn(move || {
+            let mut held = Vec::new();
+            for (n, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                server_requests
+                    .lock()
+                    .unwrap()
+                    .push(read_request(&mut stream));
+                let reply = reply(n);
+                let content_type = if reply.status == 200 {
+                    "content-type: text/event-stream\r\n"
+                } else {
+                    "content-length: 0\r\n"
+                };
+                let head = format!(
+                    "HTTP/1.1 {} Reply\r\n{}connection: close\r\n\r\n",
+                    reply.status, content_type
+                );
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(reply.body.as_bytes());
+                let _ = stream.flush();
+                if let Some((gate, rest)) = reply.rest {
+                    let _ = gate.recv();
+                    let _ = stream.write_all(rest.as_bytes());
+                    let _ = stream.flush();
+                }
+                if reply.hold {
+                    held.push(stream);
+                }
+            }
+        });
+        Self { url, requests }
+    }
+
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The head of each request received so far, with lowercase header names
+    pub(crate) fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => head.push(byte[0]),
+            _ => break,
+        }
+    }
+    String::from_utf8_lossy(&head).to_ascii_lowercase()
+}
+
+/// Get the data of the next message, skipping [`Event::Open`]s
+pub(crate) async fn next_data<S>(stream: &mut S) -> String
+where
+    S: Stream<Item = Result<Event, Error>> + Unpin,
+{
+    loop {
+        match stream.next().await {
+            Some(Ok(Event::Open(_))) => {}
+            Some(Ok(Event::Message(message))) => return message.data,
+            item => panic!("expected a message, got {:?}", item),
+        }
+    }
+}
+
+/// Poll `stream` until `ready` holds, e.g. until it received an event it buffers, failing if it
+/// yields an item in the meantime
+pub(crate) async fn pending_until<S, F>(stream: &mut S, ready: F)
+where
+    S: Stream + Unpin,
+    S::Item: Debug,
+    F: Fn(&S) -> bool,
+{
+    while !ready(stream) {
+        poll_fn(|cx| match Pin::new(&mut *stream).poll_next(cx) {
+            Poll::Ready(item) => panic!("expected no item, got {:?}", item),
+            Poll::Pending => Poll::Ready(()),
+        })
+        .await;
+        tokio::task::yield_now().await;
+    }
+}
@@ -0,0 +1,307 @@
+//! Timers for the retry, connection and idle delays. [`futures_timer`] is used by default, and the
+//! `tokio-timer` or `async-io` features reuse the timer of those runtimes instead. None of them
+//! work in the browser, so [`gloo_timers`](https://docs.rs/gloo-timers) is used on `wasm32`.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::{Instant, SystemTime};
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::{Instant, SystemTime};
+
+use core::fmt;
+#[cfg(feature = "event-source")]
+use core::future::Future;
+#[cfg(feature = "event-source")]
+use core::pin::Pin;
+#[cfg(feature = "event-source")]
+use core::task::{Context, Poll};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_core::future::BoxFuture;
+#[cfg(target_arch = "wasm32")]
+use futures_core::future::LocalBoxFuture;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(all(feature = "event-source", doc))]
+use crate::builder::EventSourceBuilder;
+
+/// A future completing after a [`Duration`], implemented by each timer backend
+#[cfg(feature = "event-source")]
+pub(crate) trait Timer: Future<Output = ()> + Unpin {
+    fn new(duration: Duration) -> Self;
+}
+
+/// The future returned by [`Clock::sleep`]
+#[cfg(not(target_arch = "wasm32"))]
+pub type Sleep = BoxFuture<'static, ()>;
+/// The future returned by [`Clock::sleep`]
+#[cfg(target_arch = "wasm32")]
+pub type Sleep = LocalBoxFuture<'static, ()>;
+
+/// A source of time, so a test can use a mock clock it advances by hand instead of waiting. The
+/// [`TimeBudget`](crate::retry::TimeBudget) and [`RateLimited`](crate::retry::RateLimited) retry
+/// policies read the current time from a clock set with their `with_clock` method.
+#[cfg_attr(
+    feature = "event-source",
+    doc = "A clock set with [`EventSourceBuilder::clock`] drives the retry, connection, idle and \
+           lifetime delays and the timestamps of an [`EventSource`](crate::EventSource)."
+)]
+/// By default the timer backend of the enabled feature and the system time are used. `Instant` is
+/// a `web_time::Instant` on `wasm32`
+pub trait Clock {
+    /// Get the current time
+    fn now(&self) -> Instant;
+
+    /// Get a future completing once `duration` passed on this clock
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// A [`Clock`] shared by a retry policy, or the system time if there is none
+#[derive(Clone)]
+pub(crate) struct SharedClock(Option<Arc<dyn Clock + Send + Sync + 'static>>);
+
+impl SharedClock {
+    pub(crate) const fn system() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn new<C: Clock + Send + Sync + 'static>(clock: C) -> Self {
+        Self(Some(Arc::new(clock)))
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        match &self.0 {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("<clock>"),
+            None => f.write_str("<system clock>"),
+        }
+    }
+}
+
+#[cfg(feature = "event-source")]
+pub(crate) type BoxedClock = Box<dyn Clock + Send + 'static>;
+
+/// The [`Clock`] of an EventSource, or the timer backend if there is none
+#[cfg(feature = "event-source")]
+pub(crate) struct ClockHandle(Option<BoxedClock>);
+
+#[cfg(feature = "event-source")]
+impl ClockHandle {
+    pub(crate) fn new(clock: Option<BoxedClock>) -> Self {
+        Self(clock)
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        match &self.0 {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    pub(crate) fn delay(&self, duration: Duration) -> Timeout {
+        match &self.0 {
+            Some(clock) => Timeout::Clock(clock.sleep(duration)),
+            None => Timeout::Delay(Delay::new(duration)),
+        }
+    }
+}
+
+/// A delay created by a [`ClockHandle`]
+#[cfg(feature = "event-source")]
+pub(crate) enum Timeout {
+    Delay(Delay),
+    Clock(Sleep),
+}
+
+#[cfg(feature = "event-source")]
+impl Future for Timeout {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        match &mut *self {
+            Self::Delay(delay) => Pin::new(delay).poll(cx),
+            Self::Clock(sleep) => sleep.as_mut().poll(cx),
+        }
+    }
+}
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    feature = "event-source",
+    not(any(
+        feature = "futures-timer",
+        feature = "tokio-timer",
+        feature = "async-io"
+    ))
+))]
+compile_error!(
+    "the `event-source` feature requires a timer, enable `futures-timer`, `tokio-timer` or `async-io`"
+);
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio-timer"))]
+mod tokio_timer {
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::time::Sleep;
+
+    /// A [`tokio`] timer, which must be polled inside a runtime with the time driver enabled
+    #[derive(Debug)]
+    pub(crate) struct Delay(Pin<Box<Sleep>>);
+
+    impl super::Timer for Delay {
+        fn new(duration: Duration) -> Self {
+            Self(Box::pin(tokio::time::sleep(duration)))
+        }
+    }
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            self.0.as_mut().poll(cx)
+        }
+    }
+}
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    feature = "async-io",
+    not(feature = "tokio-timer")
+))]
+mod async_io_timer {
+    use async_io::Timer as AsyncIoTimer;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use std::time::Duration;
+
+    /// An [`async_io`] timer, as used by `async-std` and `smol`
+    #[derive(Debug)]
+    pub(crate) struct Delay(AsyncIoTimer);
+
+    impl super::Timer for Delay {
+        fn new(duration: Duration) -> Self {
+            Self(AsyncIoTimer::after(duration))
+        }
+    }
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            Pin::new(&mut self.0).poll(cx).map(|_| ())
+        }
+    }
+}
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    feature = "futures-timer",
+    not(any(feature = "tokio-timer", feature = "async-io"))
+))]
+mod futures_timer {
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use std::time::Duration;
+
+    /// A [`futures_timer`] timer, which works with any executor
+    #[derive(Debug)]
+    pub(crate) struct Delay(::futures_timer::Delay);
+
+    impl super::Timer for Delay {
+        fn new(duration: Duration) -> Self {
+            Self(::futures_timer::Delay::new(duration))
+        }
+    }
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            Pin::new(&mut self.0).poll(cx)
+        }
+    }
+}
+
+/// The delay in milliseconds of a browser timer for `duration`. `setTimeout` takes a signed 32 bit
+/// delay and fires at once for a larger one, so longer durations are clamped to about 24.8 days
+#[cfg(any(test, all(target_arch = "wasm32", feature = "event-source")))]
+fn timeout_millis(duration: Duration) -> u32 {
+    duration.as_millis().min(i32::MAX as u128) as u32
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "event-source"))]
+mod wasm {
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use gloo_timers::future::TimeoutFuture;
+    use std::time::Duration;
+
+    /// A browser timer completing after a [`Duration`]
+    #[derive(Debug)]
+    pub(crate) struct Delay(TimeoutFuture);
+
+    impl super::Timer for Delay {
+        fn new(duration: Duration) -> Self {
+            Self(TimeoutFuture::new(super::timeout_millis(duration)))
+        }
+    }
+
+    impl Future for Delay {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            Pin::new(&mut self.0).poll(cx)
+        }
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio-timer"))]
+pub(crate) use self::tokio_timer::Delay;
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    feature = "async-io",
+    not(feature = "tokio-timer")
+))]
+pub(crate) use self::async_io_timer::Delay;
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    feature = "futures-timer",
+    not(any(feature = "tokio-timer", feature = "async-io"))
+))]
+pub(crate) use self::futures_timer::Delay;
+
+#[cfg(all(target_arch = "wasm32", feature = "event-source"))]
+pub(crate) use self::wasm::Delay;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_millis_clamped() {
+        assert_eq!(timeout_millis(Duration::from_millis(1500)), 1500);
+        assert_eq!(
+            timeout_millis(Duration::from_millis(i32::MAX as u64)),
+            i32::MAX as u32
+        );
+        assert_eq!(
+            timeout_millis(Duration::from_millis(u32::MAX as u64)),
+            i32::MAX as u32
+        );
+        assert_eq!(timeout_millis(Duration::MAX), i32::MAX as u32);
+    }
+}